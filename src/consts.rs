@@ -9,10 +9,38 @@ pub const UUID_SIZE: usize = 16;
 pub const JBD_FLAG_ESCAPE: u32 = 1;
 pub const JBD_FLAG_SAME_UUID: u32 = 2;
 pub const JBD_FLAG_LAST_TAG: u16 = 8;
-pub const JBD_FEATURE_INCOMPAT_64BIT: u32 = 1;
+pub const JBD_FEATURE_COMPAT_CHECKSUM: u32 = 1;
+pub const JBD_FEATURE_INCOMPAT_REVOKE: u32 = 1;
+pub const JBD_FEATURE_INCOMPAT_64BIT: u32 = 2;
+pub const JBD_FEATURE_INCOMPAT_ASYNC_COMMIT: u32 = 4;
+pub const JBD_FEATURE_INCOMPAT_CSUM_V2: u32 = 8;
 
 pub const JBD_USERS_SIZE: usize = 16 * 48;
 pub const JBD_MAGIC_NUMBER: u32 = 0xc03b3998;
 pub const JBD_FEATURE_INCOMPAT_CSUM_V3: u16 = 0x00000010;
 
+pub const JBD_CRC32_CHKSUM: u8 = 1;
+pub const JBD_MD5_CHKSUM: u8 = 2;
+pub const JBD_SHA1_CHKSUM: u8 = 3;
+pub const JBD_CRC32C_CHKSUM: u8 = 4;
+pub const JBD_CRC32_CHKSUM_SIZE: u8 = 4;
+pub const JBD_CHECKSUM_BYTES: usize = 32 / core::mem::size_of::<u32>();
+
+// ext4 superblock: s_feature_incompat and its needs_recovery bit
+pub const EXT4_SUPERBLOCK_OFFSET: usize = 0x400;
+pub const EXT4_FEATURE_INCOMPAT_OFFSET: usize = 0x60;
+pub const EXT4_FEATURE_INCOMPAT_RECOVER: u32 = 0x4;
+
+// By default a single transaction may use at most a quarter of the log
+pub const JBD_DEFAULT_MAX_TRANS_FRACTION: u32 = 4;
+
+// Error recorded in the superblock when a commit runs out of log space
+pub const ENOSPC: i32 = 28;
+
+// Error recorded in a transaction discarded by Handle::abort()
+pub const ECANCELED: i32 = 125;
+
 pub const BLOCK_SIZE: usize = 4096;
+
+// Number of journal blocks read per batch while scanning the log
+pub const JBD_READAHEAD_BLOCKS: u32 = 128;
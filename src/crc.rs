@@ -0,0 +1,33 @@
+// 与内核 crc32_be 一致：多项式 0x04c11db7，高位在前，不做最终取反
+
+const CRC32_BE_POLY: u32 = 0x04c1_1db7;
+
+const CRC32_BE_TABLE: [u32; 256] = crc32_be_table();
+
+const fn crc32_be_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ CRC32_BE_POLY
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+pub fn jbd_crc32_be(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        let index = ((crc >> 24) ^ byte as u32) & 0xff;
+        crc = (crc << 8) ^ CRC32_BE_TABLE[index as usize];
+    }
+    crc
+}
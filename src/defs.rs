@@ -67,27 +67,58 @@ pub struct JbdJournal {
     pub first: u32,
     pub start: u32,
     pub last: u32,
+    pub free: u32,                 // Number of unallocated blocks in the log
     pub trans_id: u32,
     pub alloc_trans_id: u32,
     pub block_size: u32,
+    pub barrier: bool,             // Issue flush/FUA around the commit block
+    pub aborted: bool,             // Journal aborted, no further handles or commits
+    pub clock: Option<Arc<dyn JbdClock>>, // Source of commit timestamps
+    pub max_trans_fraction: u32,   // A transaction may use at most 1/fraction of the log
+    pub max_trans_buffers: u32,    // Limit of log credits per transaction
+    pub max_trans_data: u32,       // Limit of data blocks per transaction, 0 for none
     pub cp_queue: CheckpointQueue, // Queue for managing checkpointing
     pub block_rec_root: BlockRecordRoot, // Root of the block record tree
     pub jbd_fs: *mut JbdFs,        // Back-reference to the JbdFs
 }
 
+// 事务的生命周期：运行中接受新句柄；锁定后只等已有句柄结束；
+// 写日志块时为 Flushing，写提交块时为 Committing；提交完成等待检查点，
+// 检查点完成后事务从日志中释放
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransState {
+    Running,
+    Locked,
+    Flushing,
+    Committing,
+    Committed,
+    Checkpointed,
+}
+
 #[derive(Debug, Clone)]
 pub struct Transaction {
     pub trans_id: u32,
+    pub state: TransState,
     pub start_iblock: u32,
     pub alloc_blocks: i32,
     pub data_cnt: i32,
     pub data_csum: u32,
     pub written_cnt: i32,
     pub error: i32,
+    pub updates: u32,             // 尚未结束的句柄数
+    pub outstanding_credits: u32, // 句柄预留的日志块数（包括已经使用的）
     pub journal: Arc<JbdJournal>,
     pub buf_queue: VecDeque<JbdBuf>,
     pub revoke_root: BTreeMap<u32, JbdRevokeRec>,
-    pub tbrec_list: Vec<JbdBlockRec>,
+    pub tbrec_root: BTreeMap<u32, JbdBlockRec>, // 按块号索引本事务的缓冲区
+}
+
+// 句柄代表一次原子的元数据更新，开始时预留日志块，所有句柄结束后事务才能提交
+#[derive(Debug)]
+pub struct Handle {
+    pub trans: Arc<RefCell<Transaction>>,
+    pub buffer_credits: u32, // 剩余可用的日志块数
+    pub jbd_fs: *mut JbdFs,
 }
 
 #[derive(Debug, Clone)]
@@ -109,14 +140,14 @@ impl CheckpointQueue {
 
 #[derive(Debug, Clone)]
 pub struct BlockRecord {
-    lba: u64,                        // 块地址
-    trans: Option<Arc<Transaction>>, // 关联的事务
-    dirty_buf_queue: Vec<JbdBuf>,    // 存储脏缓冲区的集合
+    pub lba: u32,      // 块地址
+    pub trans_id: u32, // 持有该块最新日志副本、尚未检查点的事务
 }
 
+// 记录每个块最新的日志副本在哪个已提交的事务中
 #[derive(Debug, Clone)]
 pub struct BlockRecordRoot {
-    records: BTreeMap<u32, BlockRecord>,
+    pub records: BTreeMap<u32, BlockRecord>,
 }
 impl BlockRecordRoot {
     pub fn new() -> Self {
@@ -124,13 +155,30 @@ impl BlockRecordRoot {
             records: BTreeMap::new(),
         }
     }
+
+    // 记录 trans_id 中的副本为最新，返回之前持有该块的事务
+    pub fn insert(&mut self, lba: u32, trans_id: u32) -> Option<u32> {
+        self.records
+            .insert(lba, BlockRecord { lba, trans_id })
+            .map(|rec| rec.trans_id)
+    }
+
+    pub fn get(&self, lba: u32) -> Option<&BlockRecord> {
+        self.records.get(&lba)
+    }
+
+    // 只有记录仍指向 trans_id 时才删除，更新的事务中的副本继续保留
+    pub fn remove(&mut self, lba: u32, trans_id: u32) {
+        if self.records.get(&lba).is_some_and(|rec| rec.trans_id == trans_id) {
+            self.records.remove(&lba);
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct JbdBlockRec {
-    lba: u64,
-    trans: Option<Arc<Transaction>>,
-    dirty_buf_queue: Vec<JbdBuf>,
+    pub lba: u32,
+    pub buf_idx: usize, // 该块在事务 buf_queue 中的位置
 }
 
 #[derive(Debug, Clone)]
@@ -181,9 +229,16 @@ impl Default for JbdJournal {
             first: 0,
             start: 0,
             last: 0,
+            free: 0,
             trans_id: 0,
             alloc_trans_id: 0,
             block_size: 4096,
+            barrier: true,
+            aborted: false,
+            clock: None,
+            max_trans_fraction: JBD_DEFAULT_MAX_TRANS_FRACTION,
+            max_trans_buffers: 0,
+            max_trans_data: 0,
             cp_queue: CheckpointQueue::new(),
             block_rec_root: BlockRecordRoot::new(),
             jbd_fs: core::ptr::null_mut(),
@@ -199,6 +254,7 @@ pub struct JbdFs {
     pub ext4fs: Arc<dyn Ext4Fs>,
     pub dirty: bool,
     pub curr_trans: Option<Arc<RefCell<Transaction>>>,
+    pub commit_trans: Option<Arc<RefCell<Transaction>>>, // 已锁定、正在提交的事务
 }
 
 pub struct RecoverInfo {
@@ -207,6 +263,57 @@ pub struct RecoverInfo {
     pub trans_cnt: u32,
     pub start_trans_id: u32,
     pub this_trans_id: u32,
+    pub trans_csum: u32,
+    pub commit_times: Vec<CommitTime>,
+    pub csum_failures: u32,
+    pub revoked_cnt: u32,
+    pub replayed_blocks: Vec<u64>,
+    pub planned_writes: Vec<ReplayWrite>,
+    pub dry_run: bool,
+    pub until_trans_id: Option<u32>,
+    pub target: Option<Arc<dyn BlockDevice>>,
+    pub replay_map: BTreeMap<u64, ReplayWrite>,
+    pub superseded_cnt: u32,
+    pub log_tags: Vec<ReplayWrite>,
+    pub log_revokes: Vec<RevokeEntry>,
+    pub ra_start: u32,
+    pub ra_count: u32,
+    pub ra_buf: Vec<u8>,
+}
+
+// Commit time recorded in the commit block of a recovered transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommitTime {
+    pub trans_id: u32,
+    pub commit_sec: u64,
+    pub commit_nsec: u32,
+}
+
+// One block write performed (or, in a dry run, planned) by the replay pass
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayWrite {
+    pub block: u64,
+    pub journal_block: u32,
+    pub trans_id: u32,
+    pub is_escape: bool,
+}
+
+// Summary of what recover() did, for audit logging and tests
+#[derive(Debug, Clone, Default)]
+pub struct RecoveryReport {
+    pub start_trans_id: u32,
+    pub end_trans_id: u32,
+    pub trans_cnt: u32,
+    pub replayed_cnt: u32,
+    pub revoked_cnt: u32,
+    pub superseded_cnt: u32,
+    pub csum_failures: u32,
+    pub replayed_blocks: Vec<u64>,
+    pub planned_writes: Vec<ReplayWrite>,
+    pub commit_times: Vec<CommitTime>, // 每个被恢复事务的提交时间
+    pub last_commit_sec: u64,
+    pub last_commit_nsec: u32,
+    pub dry_run: bool,
 }
 
 #[repr(C)]
@@ -217,6 +324,19 @@ pub struct JbdBhdr {
     pub sequence: u32,
 }
 
+// On-disk layout of the commit block, following the block header
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CommitBlock {
+    pub header: JbdBhdr,
+    pub chksum_type: u8,
+    pub chksum_size: u8,
+    pub padding: [u8; 2],
+    pub chksum: [u32; JBD_CHECKSUM_BYTES],
+    pub commit_sec: u64,
+    pub commit_nsec: u32,
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct JbdRevokeHeader {
@@ -230,12 +350,6 @@ pub struct RevokeEntry {
     pub trans_id: u32,
 }
 
-pub struct ReplayArg<'a> {
-    pub info: &'a mut RecoverInfo,
-    pub this_block: &'a mut u32,
-    pub this_trans_id: u32,
-}
-
 #[derive(Debug, Clone, Default)]
 #[repr(C)]
 pub struct TagInfo {
@@ -269,6 +383,22 @@ impl RecoverInfo {
             last_trans_id: 0,
             this_trans_id: 0,
             trans_cnt: 0,
+            trans_csum: !0,
+            commit_times: Vec::new(),
+            csum_failures: 0,
+            revoked_cnt: 0,
+            replayed_blocks: Vec::new(),
+            planned_writes: Vec::new(),
+            dry_run: false,
+            until_trans_id: None,
+            target: None,
+            replay_map: BTreeMap::new(),
+            superseded_cnt: 0,
+            log_tags: Vec::new(),
+            log_revokes: Vec::new(),
+            ra_start: 0,
+            ra_count: 0,
+            ra_buf: Vec::new(),
             revoke_tree: BTreeMap::new(),
         }
     }
@@ -286,7 +416,7 @@ pub struct JbdBlockTag3 {
 impl JbdBlockTag3 {
     pub fn to_be_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
-        bytes.extend_from_slice(&self.blocknr.to_le_bytes());
+        bytes.extend_from_slice(&self.blocknr.to_be_bytes());
         bytes.extend_from_slice(&self.checksum.to_be_bytes());
         bytes.extend_from_slice(&self.flags.to_be_bytes());
         bytes.extend_from_slice(&self.blocknr_high.to_be_bytes());
@@ -318,8 +448,19 @@ pub fn any_as_u8_slice<T: Sized>(p: &T) -> &[u8] {
 }
 
 pub fn trans_id_diff(id1: u32, id2: u32) -> i32 {
-    // Logic to calculate the difference between two transaction IDs
-    id1 as i32 - id2 as i32
+    // Transaction IDs wrap around at 2^32; the difference is taken modulo 2^32
+    // and interpreted as signed, so IDs less than 2^31 apart compare correctly.
+    id1.wrapping_sub(id2) as i32
+}
+
+// Is transaction id1 newer than id2? (kernel tid_gt)
+pub fn tid_gt(id1: u32, id2: u32) -> bool {
+    trans_id_diff(id1, id2) > 0
+}
+
+// Is transaction id1 the same as or newer than id2? (kernel tid_geq)
+pub fn tid_geq(id1: u32, id2: u32) -> bool {
+    trans_id_diff(id1, id2) >= 0
 }
 
 pub trait BlockDevice: Send + Sync + Any + Debug {
@@ -329,6 +470,24 @@ pub trait BlockDevice: Send + Sync + Any + Debug {
     // 将数据写入指定偏移量
     fn write_offset(&self, offset: usize, data: &[u8]);
 
+    // 从指定偏移量开始连续读取 count 个块，支持大块顺序读的设备可以覆盖
+    fn read_blocks(&self, offset: usize, count: usize) -> Vec<u8> {
+        let mut data = Vec::with_capacity(count * BLOCK_SIZE);
+        for i in 0..count {
+            data.extend_from_slice(&self.read_offset(offset + i * BLOCK_SIZE));
+        }
+        data
+    }
+
+    // 将设备易失性缓存中的数据刷写到持久介质，没有写缓存的设备无需实现
+    fn flush(&self) {}
+
+    // 以 FUA 方式写入：返回时数据已经落盘。不支持 FUA 的设备退化为写入后刷新
+    fn write_offset_fua(&self, offset: usize, data: &[u8]) {
+        self.write_offset(offset, data);
+        self.flush();
+    }
+
     // // 查找并获取一个缓冲区，如果不存在则返回 None
     // fn find_get_buffer(&self, block_num: u32) -> Option<Buffer>;
 
@@ -349,4 +508,44 @@ pub trait BlockDevice: Send + Sync + Any + Debug {
 pub trait Ext4Fs: Send + Sync + Any + Debug {
     fn get_journal_block(&self) -> Vec<u8>;
     fn get_superblock(&self) -> Vec<u8>;
-}
\ No newline at end of file
+    // 恢复完成后清除 ext4 超级块中的 needs_recovery (EXT4_FEATURE_INCOMPAT_RECOVER) 标志
+    fn clear_needs_recovery(&self);
+}
+
+// 提交块时间戳的来源，no_std 环境下由使用者提供
+pub trait JbdClock: Send + Sync + Any + Debug {
+    // 返回自 Unix 纪元以来的秒数和纳秒数
+    fn now(&self) -> (u64, u32);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tid_compare_across_2_31() {
+        let half = 1u32 << 31;
+        assert!(tid_gt(half, half - 1));
+        assert!(!tid_gt(half - 1, half));
+        assert!(tid_geq(half, half));
+        assert!(tid_gt(half + 5, half - 5));
+
+        // 相差恰好 2^31 时差值为 i32::MIN，两个方向都不算更新
+        assert!(!tid_gt(half, 0));
+        assert!(!tid_gt(0, half));
+        assert!(tid_gt(half - 1, 0));
+        assert!(!tid_geq(0, half - 1));
+    }
+
+    #[test]
+    fn tid_compare_across_2_32() {
+        assert!(tid_gt(0, u32::MAX));
+        assert!(tid_gt(5, u32::MAX - 5));
+        assert!(!tid_gt(u32::MAX, 0));
+        assert!(tid_geq(0, u32::MAX));
+        assert!(!tid_geq(u32::MAX, 0));
+        assert!(tid_geq(u32::MAX, u32::MAX));
+        assert!(!tid_gt(u32::MAX, u32::MAX));
+        assert_eq!(trans_id_diff(2, u32::MAX - 1), 4);
+    }
+}
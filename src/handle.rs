@@ -0,0 +1,148 @@
+use super::defs::*;
+use super::prelude::*;
+
+impl Handle {
+    // 把块的新内容加入事务；块第一次加入事务时消耗一个预留的日志块
+    pub fn write(&mut self, block: Ext4Block) -> Result<(), String> {
+        let jbd_fs = unsafe { &*self.jbd_fs };
+        if jbd_fs.journal.aborted {
+            return Err("Journal has been aborted".to_string());
+        }
+
+        let max_trans_data = jbd_fs.journal.max_trans_data;
+        let mut trans = self.trans.borrow_mut();
+        if trans.error != 0 {
+            return Err("Transaction has been aborted".to_string());
+        }
+
+        if !trans.jbd_trans_has_block(block.lb_id as u32) {
+            if self.buffer_credits == 0 {
+                return Err("No credits left in handle".to_string());
+            }
+            if max_trans_data != 0 && trans.data_cnt as u32 >= max_trans_data {
+                return Err("Transaction data limit reached".to_string());
+            }
+            self.buffer_credits -= 1;
+        }
+        trans.jbd_trans_set_block_dirty(block);
+        Ok(())
+    }
+
+    // 撤销记录同样写入日志，没有预留在句柄中，需要时单独申请日志空间
+    pub fn revoke(&mut self, lba: u32) -> Result<(), String> {
+        let jbd_fs = unsafe { &mut *self.jbd_fs };
+        if jbd_fs.journal.aborted {
+            return Err("Journal has been aborted".to_string());
+        }
+
+        if self.trans.borrow().error != 0 {
+            return Err("Transaction has been aborted".to_string());
+        }
+        if !self.trans.borrow().revoke_root.contains_key(&lba) {
+            jbd_fs.journal.jbd_log_wait_for_space(0, 1)?;
+        }
+
+        let mut trans = self.trans.borrow_mut();
+        trans.jbd_trans_revoke_block(lba);
+        Ok(())
+    }
+
+    // 在同一个事务中追加预留 nblocks 个日志块
+    // 超过单个事务的上限或者日志放不下时返回错误，调用者应当 restart 到新的事务中
+    pub fn extend(&mut self, nblocks: u32) -> Result<(), String> {
+        let jbd_fs = unsafe { &mut *self.jbd_fs };
+        if jbd_fs.journal.aborted {
+            return Err("Journal has been aborted".to_string());
+        }
+
+        {
+            let trans = self.trans.borrow();
+            if trans.error != 0 {
+                return Err("Transaction has been aborted".to_string());
+            }
+            if trans.state != TransState::Running {
+                return Err("Transaction is already committing".to_string());
+            }
+            if trans.outstanding_credits + nblocks > jbd_fs.journal.max_trans_buffers {
+                return Err("Handle credits exceed the transaction limit".to_string());
+            }
+        }
+        jbd_fs.journal.jbd_log_wait_for_space(nblocks, 0)?;
+
+        let mut trans = self.trans.borrow_mut();
+        trans.outstanding_credits += nblocks;
+        self.buffer_credits += nblocks;
+        Ok(())
+    }
+
+    // 结束当前句柄并提交它所在的事务（还有其他句柄时先锁定，等它们结束后才写日志），
+    // 然后在新的运行事务中预留 nblocks 个日志块。
+    // 旧句柄总是被消耗掉：失败（包括事务已被中止）时它已经结束，不能再使用
+    pub fn restart(mut self, nblocks: u32) -> Result<Handle, String> {
+        let jbd_fs = unsafe { &mut *self.jbd_fs };
+        let error = self.trans.borrow().error;
+        self.jbd_handle_stop()?;
+        if error != 0 {
+            return Err("Transaction has been aborted".to_string());
+        }
+
+        let is_running = jbd_fs
+            .curr_trans
+            .as_ref()
+            .is_some_and(|trans| Arc::ptr_eq(trans, &self.trans));
+        if is_running {
+            jbd_fs.journal.commit()?;
+        }
+
+        jbd_fs.journal.start(nblocks)
+    }
+
+    // 中止句柄所在的事务并结束句柄。同一事务中其他句柄的修改也一并丢弃，
+    // 它们之后的写入和 stop 都返回错误；新的句柄加入新的运行事务
+    pub fn abort(mut self) -> Result<(), String> {
+        self.trans.borrow_mut().abort()?;
+
+        let jbd_fs = unsafe { &mut *self.jbd_fs };
+        if jbd_fs
+            .curr_trans
+            .as_ref()
+            .is_some_and(|trans| Arc::ptr_eq(trans, &self.trans))
+        {
+            jbd_fs.curr_trans = None;
+        }
+        self.jbd_handle_stop()
+    }
+
+    // 结束句柄，归还没有用完的日志块；最后一个句柄结束后事务就可以提交了。
+    // 事务已被中止时句柄的修改没有生效，返回错误
+    pub fn stop(mut self) -> Result<(), String> {
+        let error = self.trans.borrow().error;
+        self.jbd_handle_stop()?;
+        if error != 0 {
+            return Err("Transaction has been aborted".to_string());
+        }
+        Ok(())
+    }
+
+    fn jbd_handle_stop(&mut self) -> Result<(), String> {
+        let (updates, state) = {
+            let mut trans = self.trans.borrow_mut();
+            trans.updates -= 1;
+            trans.outstanding_credits -= self.buffer_credits;
+            (trans.updates, trans.state)
+        };
+        self.buffer_credits = 0;
+
+        // 事务已被锁定等待提交，最后一个句柄结束后继续完成提交
+        let jbd_fs = unsafe { &mut *self.jbd_fs };
+        if updates == 0 && state == TransState::Locked {
+            jbd_fs.journal.jbd_journal_commit_locked()?;
+        }
+
+        // 运行的事务已经达到上限时立即提交
+        if !jbd_fs.journal.jbd_commit_busy() && jbd_fs.journal.jbd_running_trans_full(1) {
+            jbd_fs.journal.commit()?;
+        }
+        Ok(())
+    }
+}
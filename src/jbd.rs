@@ -1,6 +1,7 @@
 use crate::journal;
 
 use super::consts::*;
+use super::crc::*;
 use super::defs::*;
 use super::prelude::*;
 use super::transaction::*;
@@ -8,62 +9,343 @@ use super::transaction::*;
 impl JbdFs {
     pub fn jbd_get_fs(&self) {}
 
-    pub fn journal_start(&mut self) {
+    // 设备没有易失性写缓存（或有掉电保护）时可以关闭屏障，省去每次提交的刷新
+    pub fn set_barrier(&mut self, barrier: bool) {
+        self.journal.barrier = barrier;
+    }
+
+    // 设置提交块时间戳的时钟源
+    pub fn set_clock(&mut self, clock: Arc<dyn JbdClock>) {
+        self.journal.clock = Some(clock);
+    }
+
+    // 单个事务最多占用日志的 1/fraction，超过时自动提交
+    pub fn set_max_trans_fraction(&mut self, fraction: u32) {
+        self.journal.max_trans_fraction = fraction.max(1);
+    }
+
+    pub fn journal_start(&mut self) -> Result<(), String> {
+        // 上次运行时日志被中止过，检查文件系统之后调用 clear_err 才能继续使用
+        let errno = self.errno();
+        if errno != 0 {
+            log::error!("Journal was aborted with error {}", errno);
+            return Err("Journal was aborted".to_string());
+        }
+
+        // 日志中还有未重放的事务时先恢复，新事务的 ID 必须排在它们之后
+        if self.sb.start != 0 {
+            log::info!("Journal needs recovery.");
+            self.recover()?;
+        }
+
+        // 干净的日志中超级块的 sequence 就是下一个事务的 ID
+        let sequence = self.sb.sequence.to_be();
+
+        // 超级块中的 max_transaction 比按比例算出的上限更小时以它为准
+        let journal_len = self.sb.maxlen.to_be().saturating_sub(self.sb.first.to_be());
+        let mut max_trans_buffers = journal_len / self.journal.max_trans_fraction;
+        let max_transaction = self.sb.max_transaction.to_be();
+        if max_transaction != 0 {
+            max_trans_buffers = max_trans_buffers.min(max_transaction);
+        }
+
         let mut journal = JbdJournal {
-            first: self.sb.first,
-            start: self.sb.first,
-            last: self.sb.first,
-            trans_id: self.sb.sequence + 1,
-            alloc_trans_id: 0,
-            block_size: self.sb.blocksize,
+            first: self.sb.first.to_be(),
+            start: self.sb.first.to_be(),
+            last: self.sb.first.to_be(),
+            free: journal_len,
+            trans_id: sequence,
+            alloc_trans_id: sequence,
+            block_size: self.sb.blocksize.to_be(),
+            barrier: self.journal.barrier,
+            aborted: false,
+            clock: self.journal.clock.clone(),
+            max_trans_fraction: self.journal.max_trans_fraction,
+            max_trans_buffers,
+            max_trans_data: self.sb.max_trandata.to_be(),
             cp_queue: CheckpointQueue::new(),
             block_rec_root: BlockRecordRoot::new(),
             jbd_fs: self,
         };
 
+        // 描述符块和提交块同样占用日志，一个满额的事务必须能完整放进日志
+        while journal.max_trans_buffers > 0
+            && journal.jbd_log_blocks(journal.max_trans_buffers, 0) > journal_len
+        {
+            journal.max_trans_buffers -= 1;
+        }
+
         journal.jbd_journal_write_sb();
 
         self.journal = journal;
-    }
 
-    pub fn trans_start(&mut self) {
-        let new_trans = Transaction::new(Arc::new(self.journal.clone()));
-        self.curr_trans = Some(Arc::new(RefCell::new(new_trans)));
+        Ok(())
     }
 
-    pub fn trans_stop(&mut self) {
-        let curr_trans = self.curr_trans.as_mut().unwrap();
-        let mut trans = curr_trans.borrow_mut();
-        self.journal.commit_trans(&mut trans);
+    // 卸载时调用：提交运行的事务，对所有事务做检查点，然后写入 start 为 0 的超级块，
+    // 下次挂载时日志是干净的，不需要恢复
+    pub fn journal_stop(&mut self) -> Result<(), String> {
+        if self.journal.aborted {
+            return Err("Journal has been aborted".to_string());
+        }
+
+        for trans in self.curr_trans.iter().chain(self.commit_trans.iter()) {
+            if trans.borrow().updates > 0 {
+                return Err("Transaction still has running handles".to_string());
+            }
+        }
+
+        self.journal.jbd_journal_commit_locked()?;
+        self.journal.commit()?;
+        self.journal.jbd_journal_purge_cp_trans(true, false);
 
         self.journal.jbd_journal_write_sb();
+        self.bdev.flush();
+
+        Ok(())
+    }
+
+    // 简化接口：不使用句柄，直接向当前运行的事务写入，trans_stop 时提交
+    pub fn trans_start(&mut self) {
+        self.journal.jbd_running_trans();
+    }
+
+    pub fn trans_stop(&mut self) -> Result<(), String> {
+        self.journal.commit()
     }
 
-    pub fn write_trans(&mut self, block: Ext4Block) {
-        let curr_trans = self.curr_trans.as_mut().unwrap();
+    pub fn write_trans(&mut self, block: Ext4Block) -> Result<(), String> {
+        if self.journal.aborted {
+            return Err("Journal has been aborted".to_string());
+        }
+
+        let lba = block.lb_id as u32;
+        let is_new = self
+            .curr_trans
+            .as_ref()
+            .is_none_or(|trans| !trans.borrow().jbd_trans_has_block(lba));
+
+        // 只有新加入事务的块需要日志空间；运行的事务已满时先提交，新块写入下一个事务。
+        // 前一个事务仍在提交、运行的事务又已满时返回错误；
+        // 日志放不下时拒绝写入，而不是等到提交时才发现空间不足
+        if is_new {
+            if self.journal.jbd_running_trans_full(1) {
+                self.journal.commit()?;
+            }
+            self.journal.jbd_log_wait_for_space(1, 0)?;
+        }
+
+        let curr_trans = self.journal.jbd_running_trans();
         let mut trans = curr_trans.borrow_mut();
+        if !trans.jbd_trans_has_block(lba) {
+            trans.outstanding_credits += 1;
+        }
         trans.jbd_trans_set_block_dirty(block);
+        Ok(())
     }
 
-    pub fn recover(&mut self) -> Result<(), String> {
-        if self.sb.start == 0 {
-            log::info!("Journal is empty.");
-            return Ok(());
+    pub fn revoke_trans(&mut self, lba: u32) -> Result<(), String> {
+        if self.journal.aborted {
+            return Err("Journal has been aborted".to_string());
         }
+
+        // 撤销记录同样写入日志，需要先预留空间
+        let is_new = self
+            .curr_trans
+            .as_ref()
+            .is_none_or(|trans| !trans.borrow().revoke_root.contains_key(&lba));
+        if is_new {
+            self.journal.jbd_log_wait_for_space(0, 1)?;
+        }
+
+        let curr_trans = self.journal.jbd_running_trans();
+        let mut trans = curr_trans.borrow_mut();
+        trans.jbd_trans_revoke_block(lba);
+        Ok(())
+    }
+
+    // 中止日志：错误码记录到超级块，此后不再接受句柄和提交，日志变为只读，
+    // 下次挂载时 journal_start 报告日志曾被中止
+    pub fn abort(&mut self, err: i32) {
+        if self.journal.aborted {
+            return;
+        }
+
+        log::error!("Aborting journal with error {}", err);
+        self.journal.aborted = true;
+        for trans in self.curr_trans.iter().chain(self.commit_trans.iter()) {
+            trans.borrow_mut().error = err;
+        }
+
+        self.sb.error_val = err.to_be();
+        self.sb.sync_to_disk(&self.bdev);
+        self.bdev.flush();
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.journal.aborted
+    }
+
+    // 超级块中记录的错误码，0 表示日志没有被中止过
+    pub fn errno(&self) -> i32 {
+        self.sb.error_val.to_be()
+    }
+
+    // 检查并修复文件系统之后清除超级块中记录的错误
+    pub fn clear_err(&mut self) {
+        self.sb.error_val = 0;
+        self.sb.sync_to_disk(&self.bdev);
+        self.bdev.flush();
+    }
+
+    // 读取块的最新内容：依次查找运行的事务、正在提交的事务和已提交但尚未检查点的事务，
+    // 都没有时才读原位置
+    pub fn read_block(&self, lba: u32) -> Vec<u8> {
+        for trans in self.curr_trans.iter().chain(self.commit_trans.iter()) {
+            if let Some(data) = trans.borrow().jbd_trans_get_block(lba) {
+                return data.clone();
+            }
+        }
+
+        if let Some(rec) = self.journal.block_rec_root.get(lba) {
+            let trans = self
+                .journal
+                .cp_queue
+                .queue
+                .iter()
+                .find(|trans| trans.trans_id == rec.trans_id);
+            if let Some(data) = trans.and_then(|trans| trans.jbd_trans_get_block(lba)) {
+                return data.clone();
+            }
+        }
+
+        self.bdev.read_offset(lba as usize * BLOCK_SIZE)
+    }
+
+    pub fn recover(&mut self) -> Result<RecoveryReport, String> {
         let mut info = RecoverInfo::new();
+        let report = self.jbd_recover(&mut info)?;
+        if report.trans_cnt == 0 && self.sb.start == 0 {
+            return Ok(report);
+        }
 
-        self.iterate_log(&mut info, "ACTION_SCAN")?;
-        // self.iterate_log(&mut info, "ACTION_REVOKE")?;
-        self.iterate_log(&mut info, "ACTION_RECOVER")?;
+        // 走到这里重放的块都已刷新到设备，此前任何时刻崩溃，
+        // 超级块仍指向原来的日志，下次挂载会重新完整地恢复一遍
 
+        // 跳过第一个未提交的事务 ID，避免与日志中残留的不完整事务混淆
         self.sb.start = 0;
-        self.sb.sequence = info.last_trans_id;
-        // self.inode_ref.fs.sb.features_incompatible = features_incompatible;
+        self.sb.sequence = info.this_trans_id.wrapping_add(1).to_be();
         self.dirty = true;
 
-        log::info!("Recovery complete.");
+        // 先让日志超级块落盘，再清除文件系统的 needs_recovery 标志；
+        // 反过来的话，中途崩溃会留下一个未恢复却不再要求恢复的文件系统
+        self.sb.sync_to_disk(&self.bdev);
+        self.bdev.flush();
+        self.ext4fs.clear_needs_recovery();
 
-        Ok(())
+        Ok(report)
+    }
+
+    // 执行扫描、撤销和重放三遍，但不写设备也不修改超级块，
+    // 返回的报告中 planned_writes 列出恢复将要写入的块
+    pub fn recover_dry_run(&self) -> Result<RecoveryReport, String> {
+        let mut info = RecoverInfo::new();
+        info.dry_run = true;
+        self.jbd_recover(&mut info)
+    }
+
+    // 只重放 trans_id 及之前的事务，之后的事务留在日志中不动，超级块也不修改，
+    // 用于逐个事务二分定位引入损坏的事务
+    pub fn recover_until(&self, trans_id: u32) -> Result<RecoveryReport, String> {
+        let mut info = RecoverInfo::new();
+        info.until_trans_id = Some(trans_id);
+        self.jbd_recover(&mut info)
+    }
+
+    // 把重放的块写到另一个设备（例如写时复制的覆盖层或镜像副本），
+    // 日志所在的设备和超级块保持不变，便于取证
+    pub fn recover_into(&self, target: Arc<dyn BlockDevice>) -> Result<RecoveryReport, String> {
+        let mut info = RecoverInfo::new();
+        info.target = Some(target);
+        self.jbd_recover(&mut info)
+    }
+
+    fn jbd_recover(&self, info: &mut RecoverInfo) -> Result<RecoveryReport, String> {
+        if self.sb.start == 0 {
+            log::info!("Journal is empty.");
+            let sequence = self.sb.sequence.to_be();
+            return Ok(RecoveryReport {
+                start_trans_id: sequence,
+                end_trans_id: sequence,
+                dry_run: info.dry_run,
+                ..Default::default()
+            });
+        }
+
+        self.iterate_log(info, "ACTION_SCAN")?;
+
+        // 撤销和重放两遍都在 last_trans_id 处停止，截断之后更晚事务中的撤销记录也不会生效
+        if let Some(until) = info.until_trans_id {
+            if info.trans_cnt > 0 && tid_gt(info.last_trans_id, until) {
+                if tid_gt(info.start_trans_id, until) {
+                    info.trans_cnt = 0;
+                } else {
+                    info.trans_cnt = trans_id_diff(until, info.start_trans_id) as u32 + 1;
+                }
+                info.last_trans_id = until;
+                info.commit_times.truncate(info.trans_cnt as usize);
+                log::info!("Replaying up to transaction {}", until);
+            }
+        }
+
+        self.iterate_log(info, "ACTION_REVOKE")?;
+        self.iterate_log(info, "ACTION_RECOVER")?;
+
+        // 每个块只写一次最新的日志副本。副本按它在日志中的位置读取，
+        // 相邻的副本经过预读窗口成批读入，而不是按块号顺序逐块读日志
+        let writes: Vec<ReplayWrite> =
+            core::mem::take(&mut info.replay_map).into_values().collect();
+        if !info.dry_run {
+            let mut by_log_pos: Vec<&ReplayWrite> = writes.iter().collect();
+            by_log_pos.sort_by_key(|write| write.journal_block);
+            info.ra_count = 0;
+            for write in by_log_pos {
+                self.jbd_replay_write(info, write);
+            }
+            info.replayed_blocks.sort_unstable();
+        }
+        info.planned_writes = writes;
+
+        // 重放只读日志、只写块的原位置，中途崩溃后从头再来一遍结果相同；
+        // 但必须等这些写入落盘之后，调用者才能清除超级块中的 start
+        if !info.dry_run && !info.planned_writes.is_empty() {
+            info.target.as_ref().unwrap_or(&self.bdev).flush();
+        }
+
+        let last_commit = info.commit_times.last().copied();
+        let report = RecoveryReport {
+            start_trans_id: info.start_trans_id,
+            end_trans_id: info.last_trans_id,
+            trans_cnt: info.trans_cnt,
+            replayed_cnt: info.planned_writes.len() as u32,
+            revoked_cnt: info.revoked_cnt,
+            superseded_cnt: info.superseded_cnt,
+            csum_failures: info.csum_failures,
+            replayed_blocks: core::mem::take(&mut info.replayed_blocks),
+            planned_writes: core::mem::take(&mut info.planned_writes),
+            commit_times: core::mem::take(&mut info.commit_times),
+            last_commit_sec: last_commit.map_or(0, |c| c.commit_sec),
+            last_commit_nsec: last_commit.map_or(0, |c| c.commit_nsec),
+            dry_run: info.dry_run,
+        };
+
+        if info.dry_run {
+            log::info!("Recovery dry run complete: {:?}", report);
+        } else {
+            log::info!("Recovery complete: {:?}", report);
+        }
+
+        Ok(report)
     }
 
     // layout
@@ -87,8 +369,28 @@ impl JbdFs {
     // +------------------+
     pub fn iterate_log(&self, info: &mut RecoverInfo, action: &str) -> Result<(), String> {
         log::info!("Iterating log: {}", action);
+
+        if action != "ACTION_SCAN" {
+            if info.trans_cnt == 0 {
+                log::info!("No transactions to recover.");
+            } else if action == "ACTION_REVOKE" {
+                self.jbd_build_revoke_tree(info);
+            } else if action == "ACTION_RECOVER" {
+                // 描述符块已在扫描时缓存，这里不再读日志
+                for tag in core::mem::take(&mut info.log_tags) {
+                    if tid_gt(tag.trans_id, info.last_trans_id) {
+                        break;
+                    }
+                    self.jbd_replay_block_tags(info, tag);
+                }
+            }
+            log::info!("End of journal");
+            return Ok(());
+        }
+
         let mut log_end = false;
-        let mut this_block = self.sb.start.to_be();
+        let start_block = self.sb.start.to_be();
+        let mut this_block = start_block;
         let mut this_trans_id = self.sb.sequence.to_be();
 
         log::debug!(
@@ -97,28 +399,34 @@ impl JbdFs {
             this_block
         );
 
-        // let mut start_trans_id =
-        if action == "ACTION_SCAN" {
-            info.trans_cnt = 0;
-        } else if info.trans_cnt == 0 {
-            log::info!("No transactions to recover.");
-            log_end = true;
-        }
+        info.trans_cnt = 0;
+        info.trans_csum = !0;
+        info.commit_times.clear();
+        info.log_tags.clear();
+        info.log_revokes.clear();
 
         // log::info!("Start of journal at trans id: {}", this_trans_id);
 
         while !log_end {
-            let block = self.bdev.read_offset((this_block as usize) * BLOCK_SIZE);
+            let block = self.jbd_read_log_block(info, this_block);
 
             let header = block.as_ptr() as *const JbdBhdr; // Cast data to JbdBhdr
 
-            if action != "ACTION_SCAN" && trans_id_diff(this_trans_id, info.last_trans_id) > 0 {
+            if unsafe { (*header).magic.to_be() } != JBD_MAGIC_NUMBER {
+                log::info!("Invalid magic number found in journal.");
                 log_end = true;
                 continue;
             }
 
-            if unsafe { (*header).magic.to_be() } != JBD_MAGIC_NUMBER {
-                log::info!("Invalid magic number found in journal.");
+            // 序号与期望的事务不符，说明这是上一圈留下的旧块，日志到此为止
+            let sequence = unsafe { (*header).sequence.to_be() };
+            if sequence != this_trans_id {
+                log::info!(
+                    "Sequence mismatch at block {:x?}: found {}, expected {}",
+                    this_block,
+                    sequence,
+                    this_trans_id
+                );
                 log_end = true;
                 continue;
             }
@@ -127,58 +435,124 @@ impl JbdFs {
             match blocktype {
                 JBD_DESCRIPTOR_BLOCK => {
                     // log::info!("Descriptor block: {:x?}", this_block);
-                    if action == "ACTION_RECOVER" {
-                        // log::info!("Replaying descriptor block: {:x?}", this_block);
-                        let mut replay_arg = ReplayArg {
-                            info,
-                            this_block: &mut this_block,
-                            this_trans_id: this_trans_id,
-                        };
-
-                        self.jbd_replay_descriptor_block(header, &mut replay_arg);
-                    } else {
-                        self.debug_descriptor_block(header, &mut this_block);
+                    let desc_block = this_block;
+                    self.debug_descriptor_block(header, &mut this_block, this_trans_id, info);
+
+                    if self.has_trans_csum() {
+                        self.jbd_calc_chksums(info, &block, desc_block, this_block);
                     }
                 }
                 JBD_COMMIT_BLOCK => {
                     // log::info!("Commit block: {:x?}", this_block);
-                    this_trans_id += 1;
-
-                    if action == "ACTION_SCAN" {
-                        info.trans_cnt += 1;
+                    let commit = CommitBlock::from_be_bytes(&block)?;
+
+                    if self.has_trans_csum() {
+                        if commit.chksum_type == JBD_CRC32_CHKSUM
+                            && commit.chksum_size == JBD_CRC32_CHKSUM_SIZE
+                        {
+                            // 校验和不符说明事务没有完整落盘（异步提交没有刷新屏障）
+                            // 或者日志已损坏，日志到此为止
+                            let found_csum = commit.chksum[0];
+                            if found_csum != info.trans_csum {
+                                if self.has_feature(JBD_FEATURE_INCOMPAT_ASYNC_COMMIT) {
+                                    log::info!(
+                                        "Commit checksum mismatch in transaction {}: found {:x}, expected {:x}",
+                                        this_trans_id,
+                                        found_csum,
+                                        info.trans_csum
+                                    );
+                                } else {
+                                    log::warn!(
+                                        "Journal corrupted: checksum mismatch in transaction {}: found {:x}, expected {:x}",
+                                        this_trans_id,
+                                        found_csum,
+                                        info.trans_csum
+                                    );
+                                }
+                                info.csum_failures += 1;
+                                log_end = true;
+                                continue;
+                            }
+                        } else {
+                            log::warn!(
+                                "Unsupported checksum type {} in transaction {}, not verified",
+                                commit.chksum_type,
+                                this_trans_id
+                            );
+                        }
                     }
+                    info.trans_csum = !0;
+
+                    log::info!(
+                        "Commit block: {:x?} trans {} committed at {}.{:09}",
+                        this_block,
+                        this_trans_id,
+                        commit.commit_sec,
+                        commit.commit_nsec
+                    );
+                    info.commit_times.push(CommitTime {
+                        trans_id: this_trans_id,
+                        commit_sec: commit.commit_sec,
+                        commit_nsec: commit.commit_nsec,
+                    });
+
+                    this_trans_id = this_trans_id.wrapping_add(1);
+                    info.trans_cnt += 1;
                 }
                 JBD_REVOKE_BLOCK => {
                     log::info!("Revoke block: {:x?}", this_block);
-                    if action == "ACTION_REVOKE" {
-                        // self.jbd_build_revoke_tree(info, header);
-                        // Process revoke block
-                    }
+                    self.jbd_scan_revoke_block(info, &block, this_trans_id)?;
                 }
                 _ => log_end = true,
             }
 
             this_block += 1;
-            if this_block == self.sb.start {
+            self.wrap(&mut this_block);
+            if this_block == start_block {
                 log_end = true;
             }
         }
 
         log::info!("End of journal");
-        if action == "ACTION_SCAN" {
-            info.start_trans_id = self.sb.sequence;
-            info.last_trans_id = if trans_id_diff(this_trans_id, self.sb.sequence) > 0 {
-                this_trans_id - 1
-            } else {
-                this_trans_id
-            };
-        }
+
+        // 没有提交块的最后一个事务是不完整的，不计入 last_trans_id，也不会被重放
+        info.start_trans_id = self.sb.sequence.to_be();
+        info.this_trans_id = this_trans_id;
+        info.last_trans_id = if tid_gt(this_trans_id, info.start_trans_id) {
+            this_trans_id.wrapping_sub(1)
+        } else {
+            this_trans_id
+        };
 
         Ok(())
     }
 
-    fn debug_descriptor_block(&self, header: *const JbdBhdr, iblock: &mut u32) {
-        let tag_bytes = 0x8;
+    // 以 JBD_READAHEAD_BLOCKS 为单位批量顺序读取日志，命中当前窗口时不再访问设备
+    fn jbd_read_log_block(&self, info: &mut RecoverInfo, iblock: u32) -> Vec<u8> {
+        let in_window = iblock >= info.ra_start && iblock - info.ra_start < info.ra_count;
+        if !in_window {
+            // 一次批量读取不越过日志末尾，绕回之后从 first 重新开始一批
+            let maxlen = self.sb.maxlen.to_be();
+            let count = maxlen.saturating_sub(iblock).clamp(1, JBD_READAHEAD_BLOCKS);
+            info.ra_buf = self
+                .bdev
+                .read_blocks(iblock as usize * BLOCK_SIZE, count as usize);
+            info.ra_start = iblock;
+            info.ra_count = count;
+        }
+
+        let offset = (iblock - info.ra_start) as usize * BLOCK_SIZE;
+        info.ra_buf[offset..offset + BLOCK_SIZE].to_vec()
+    }
+
+    fn debug_descriptor_block(
+        &self,
+        header: *const JbdBhdr,
+        iblock: &mut u32,
+        trans_id: u32,
+        info: &mut RecoverInfo,
+    ) {
+        let tag_bytes = self.jbd_tag_bytes();
         let mut tag_ptr = unsafe { header.offset(1) as *const u8 };
         let mut tag_tbl_size = BLOCK_SIZE as isize - core::mem::size_of::<JbdBhdr>() as isize;
 
@@ -192,6 +566,14 @@ impl JbdFs {
             }
             self.jbd_display_block_tags(&tag_info, iblock);
 
+            // 缓存标签，撤销和重放两遍不必再读描述符块
+            info.log_tags.push(ReplayWrite {
+                block: tag_info.block,
+                journal_block: *iblock,
+                trans_id,
+                is_escape: tag_info.is_escape,
+            });
+
             if tag_info.last_tag {
                 break;
             }
@@ -200,77 +582,123 @@ impl JbdFs {
             tag_tbl_size -= tag_info.tag_bytes as isize;
         }
     }
-    fn jbd_replay_block_tags(&self, tag_info: &TagInfo, replay_arg: &mut ReplayArg) {
-        *replay_arg.this_block += 1;
 
-        // self.wrap(replay_arg.this_block);
+    // 解析撤销块中的块号并缓存，撤销那一遍据此建立撤销树
+    fn jbd_scan_revoke_block(
+        &self,
+        info: &mut RecoverInfo,
+        block: &[u8],
+        trans_id: u32,
+    ) -> Result<(), String> {
+        let rec_size = if self.has_feature(JBD_FEATURE_INCOMPAT_64BIT) { 8 } else { 4 };
+        let hdr_size = core::mem::size_of::<JbdRevokeHeader>();
+        let count_off = core::mem::size_of::<JbdBhdr>();
+        let count = u32::from_be_bytes(block[count_off..count_off + 4].try_into().unwrap()) as usize;
+        if count < hdr_size || count > BLOCK_SIZE {
+            return Err("Invalid revoke block count".to_string());
+        }
+
+        let mut offset = hdr_size;
+        while offset + rec_size <= count {
+            let lba = if rec_size == 8 {
+                u64::from_be_bytes(block[offset..offset + 8].try_into().unwrap())
+            } else {
+                u32::from_be_bytes(block[offset..offset + 4].try_into().unwrap()) as u64
+            };
+            offset += rec_size;
+
+            info.log_revokes.push(RevokeEntry { block: lba, trans_id });
+        }
+
+        Ok(())
+    }
+
+    // 记录每个被撤销的块号；同一个块被多次撤销时保留最新的事务 ID
+    fn jbd_build_revoke_tree(&self, info: &mut RecoverInfo) {
+        for rec in &info.log_revokes {
+            if tid_gt(rec.trans_id, info.last_trans_id) {
+                break;
+            }
+
+            match info.revoke_tree.get_mut(&rec.block) {
+                Some(entry) if tid_gt(entry.trans_id, rec.trans_id) => {}
+                Some(entry) => entry.trans_id = rec.trans_id,
+                None => {
+                    info.revoke_tree.insert(
+                        rec.block,
+                        RevokeEntry {
+                            block: rec.block,
+                            trans_id: rec.trans_id,
+                        },
+                    );
+                }
+            }
+        }
+    }
 
+    // 按日志中的顺序把描述符块及其后的数据块累加进事务校验和
+    fn jbd_calc_chksums(&self, info: &mut RecoverInfo, desc: &[u8], desc_block: u32, last_block: u32) {
+        info.trans_csum = jbd_crc32_be(info.trans_csum, desc);
+        let mut iblock = desc_block;
+        while iblock != last_block {
+            iblock += 1;
+            self.wrap(&mut iblock);
+            let data = self.jbd_read_log_block(info, iblock);
+            info.trans_csum = jbd_crc32_be(info.trans_csum, &data);
+        }
+    }
+    fn jbd_replay_block_tags(&self, info: &mut RecoverInfo, write: ReplayWrite) {
         // Check if we should replay this block
-        let revoke_entry = replay_arg.info.revoke_tree.get(&tag_info.block);
-        if let Some(entry) = revoke_entry {
-            if trans_id_diff(replay_arg.this_trans_id, entry.trans_id) <= 0 {
+        if let Some(entry) = info.revoke_tree.get(&write.block) {
+            if tid_geq(entry.trans_id, write.trans_id) {
                 // Skip replaying this block
+                info.revoked_cnt += 1;
                 return;
             }
         }
-        let mut journal_block = self
-            .bdev
-            .read_offset((*replay_arg.this_block as usize) * BLOCK_SIZE);
+
+        // 同一个块在更晚的事务中再次出现时，较早的副本不必写入
+        if info.replay_map.insert(write.block, write).is_some() {
+            info.superseded_cnt += 1;
+        }
+    }
+
+    fn jbd_replay_write(&self, info: &mut RecoverInfo, write: &ReplayWrite) {
+        let journal_block = self.jbd_read_log_block(info, write.journal_block);
         let mut ext4_block_data = vec![0u8; BLOCK_SIZE]; // Placeholder for the actual block data
 
         // Special handling for different blocks
-        if tag_info.block != 0 {
+        if write.block != 0 {
             // Regular block
             ext4_block_data.copy_from_slice(&journal_block);
-            if tag_info.is_escape {
-                let mut bhdr = unsafe { *(ext4_block_data.as_ptr() as *mut JbdBhdr) };
-                log::info!("bhdr: {:x?}", bhdr);
-                bhdr.magic = JBD_MAGIC_NUMBER.to_be();
+            if write.is_escape {
+                // 写入日志时被清零的魔数需要还原
+                ext4_block_data[0..4].copy_from_slice(&JBD_MAGIC_NUMBER.to_be_bytes());
             }
 
-            // Write back the modified block data
-            self.bdev.write_offset(
-                (*replay_arg.this_block as usize) * BLOCK_SIZE,
+            // Write the logged copy back to its home location
+            let target = info.target.as_ref().unwrap_or(&self.bdev);
+            target.write_offset(
+                (write.block as usize) * BLOCK_SIZE,
                 &ext4_block_data,
             );
+            info.replayed_blocks.push(write.block);
         } else {
             // Superblock special handling
         }
     }
     fn wrap(&self, iblock: &mut u32) {
-        // if *iblock >= self.sb.maxlen {
-        //     *iblock -= self.sb.maxlen - self.sb.first;
-        // }
+        let maxlen = self.sb.maxlen.to_be();
+        let first = self.sb.first.to_be();
+        if *iblock >= maxlen {
+            *iblock -= maxlen - first;
+        }
     }
     fn jbd_display_block_tags(&self, tag_info: &TagInfo, iblock: &mut u32) {
         log::info!("Block in block_tag: {}", tag_info.block);
         *iblock += 1;
         self.wrap(iblock);
     }
-    pub fn jbd_replay_descriptor_block(&self, header: *const JbdBhdr, replay_arg: &mut ReplayArg) {
-        let tag_bytes = 0x8; // For demonstration
-        let mut tag_ptr = unsafe { header.offset(1) as *const u8 };
-        let mut tag_tbl_size = BLOCK_SIZE as isize - core::mem::size_of::<JbdBhdr>() as isize;
-
-        while tag_tbl_size > 0 {
-            let mut tag_info = TagInfo::new();
-            if let Err(e) =
-                self.jbd_extract_block_tag(tag_ptr, tag_bytes, tag_tbl_size as usize, &mut tag_info)
-            {
-                log::info!("Error extracting block tag: {}", e);
-                break;
-            }
-            self.jbd_replay_block_tags(&tag_info, replay_arg);
-
-            if tag_info.last_tag {
-                break;
-            }
-
-            tag_ptr = unsafe { tag_ptr.offset(tag_info.tag_bytes as isize) };
-            tag_tbl_size -= tag_info.tag_bytes as isize;
-        }
-    }
-
     pub fn jbd_extract_block_tag(
         &self,
         tag_ptr: *const u8,
@@ -284,8 +712,8 @@ impl JbdFs {
 
         let jbdtag = unsafe { &*(tag_ptr as *const JbdBlockTag3) };
 
-        log::info!("blocknr: {:x?}", jbdtag.blocknr.to_le());
-        let blocknr = jbd_get32_le(tag_ptr).to_le();
+        log::info!("blocknr: {:x?}", jbdtag.blocknr.to_be());
+        let blocknr = jbd_get32(tag_ptr);
 
         let flags = jbd_get32(unsafe { tag_ptr.offset(4) });
 
@@ -363,16 +791,25 @@ impl JbdFs {
     }
 
     pub fn has_feature(&self, feature: u32) -> bool {
-        false
+        self.sb.feature_incompat.to_be() & feature != 0
+    }
+
+    pub fn has_compat_feature(&self, feature: u32) -> bool {
+        self.sb.feature_compat.to_be() & feature != 0
+    }
+
+    // 提交块中是否记录整个事务的 crc32（v1 校验和，异步提交同样依赖它）
+    pub fn has_trans_csum(&self) -> bool {
+        self.has_compat_feature(JBD_FEATURE_COMPAT_CHECKSUM)
+            || self.has_feature(JBD_FEATURE_INCOMPAT_ASYNC_COMMIT)
     }
 
     pub fn jbd_tag_bytes(&self) -> usize {
         // 根据 journal 特性返回合适的标签大小
-        if self.has_feature(JBD_FEATURE_INCOMPAT_CSUM_V3 as u32) {
+        if self.has_feature(JBD_FEATURE_INCOMPAT_64BIT) {
             core::mem::size_of::<JbdBlockTag3>()
         } else {
-            4
-            // core::mem::size_of::<JbdBlockTag>()
+            core::mem::size_of::<JbdBlockTag3>() - 4
         }
     }
 }
@@ -384,9 +821,3 @@ fn jbd_get32(ptr: *const u8) -> u32 {
     u32::from_be_bytes(slice.try_into().expect("Slice should have a length of 4"))
 }
 
-fn jbd_get32_le(ptr: *const u8) -> u32 {
-    assert!(!ptr.is_null(), "Pointer must not be null");
-
-    let slice = unsafe { core::slice::from_raw_parts(ptr, 4) };
-    u32::from_le_bytes(slice.try_into().expect("Slice should have a length of 4"))
-}
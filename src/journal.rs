@@ -1,6 +1,7 @@
 use super::defs::*;
 use super::prelude::*;
 use super::consts::*;
+use super::crc::*;
 
 impl JbdJournal {
     pub fn new() -> Self {
@@ -8,9 +9,16 @@ impl JbdJournal {
             first: 0,
             start: 0,
             last: 0,
+            free: 0,
             trans_id: 0,
             alloc_trans_id: 0,
             block_size: 4096,
+            barrier: true,
+            aborted: false,
+            clock: None,
+            max_trans_fraction: JBD_DEFAULT_MAX_TRANS_FRACTION,
+            max_trans_buffers: 0,
+            max_trans_data: 0,
             cp_queue: CheckpointQueue::new(),
             block_rec_root: BlockRecordRoot::new(),
             jbd_fs: core::ptr::null_mut(),
@@ -23,8 +31,15 @@ impl JbdJournal {
         // Here, we're just simulating the update of the journal's superblock within the JbdFs structure.
         let jbd_fs = unsafe { &mut *self.jbd_fs };
 
-        jbd_fs.sb.start = self.start.to_be();
-        jbd_fs.sb.sequence = self.trans_id.to_be();
+        // start/sequence 描述最老的尚未检查点的事务；没有这样的事务时
+        // start 为 0，表示日志是干净的，sequence 是下一个事务的 ID
+        if self.cp_queue.queue.is_empty() {
+            jbd_fs.sb.start = 0;
+            jbd_fs.sb.sequence = self.alloc_trans_id.to_be();
+        } else {
+            jbd_fs.sb.start = self.start.to_be();
+            jbd_fs.sb.sequence = self.trans_id.to_be();
+        }
 
         jbd_fs.dirty = true; // Marking the filesystem as dirty, meaning changes need to be written to disk.
 
@@ -32,193 +47,517 @@ impl JbdJournal {
         jbd_fs.sb.sync_to_disk(bdev);
     }
 
+    // 日志为空时提交的第一个事务：提交块之前先让超级块的 start 指向它并落盘。
+    // 否则提交块已经持久化而超级块还记录着空日志时掉电，恢复会丢失这个事务
+    fn jbd_journal_write_sb_tail(&mut self, start: u32, sequence: u32) {
+        let jbd_fs = unsafe { &mut *self.jbd_fs };
+        jbd_fs.sb.start = start.to_be();
+        jbd_fs.sb.sequence = sequence.to_be();
+        jbd_fs.dirty = true;
+
+        jbd_fs.sb.sync_to_disk(&jbd_fs.bdev);
+        if self.barrier {
+            jbd_fs.bdev.flush();
+        }
+    }
+
+    // 开始一个句柄并预留 nblocks 个日志块，句柄加入当前运行的事务
+    pub fn start(&mut self, nblocks: u32) -> Result<Handle, String> {
+        if self.aborted {
+            return Err("Journal has been aborted".to_string());
+        }
+
+        if nblocks > self.max_trans_buffers {
+            log::warn!(
+                "Handle wants {} credits, transaction limit is {}",
+                nblocks,
+                self.max_trans_buffers
+            );
+            return Err("Handle credits exceed the transaction limit".to_string());
+        }
+
+        // 运行的事务放不下这个句柄时先提交它，句柄加入新的运行事务。
+        // 前一个事务还在等待句柄结束时不能提交，commit 返回错误，句柄不会超过上限
+        if self.jbd_running_trans_full(nblocks) {
+            self.commit()?;
+        }
+
+        self.jbd_log_wait_for_space(nblocks, 0)?;
+
+        let trans = self.jbd_running_trans();
+        {
+            let mut trans = trans.borrow_mut();
+            trans.updates += 1;
+            trans.outstanding_credits += nblocks;
+        }
+
+        Ok(Handle {
+            trans,
+            buffer_credits: nblocks,
+            jbd_fs: self.jbd_fs,
+        })
+    }
+
+    // 返回当前运行的事务，没有时新建一个。
+    // 被中止的运行事务不再接收修改，之后的写入进入新的运行事务
+    pub fn jbd_running_trans(&mut self) -> Arc<RefCell<Transaction>> {
+        let jbd_fs = unsafe { &mut *self.jbd_fs };
+        if jbd_fs
+            .curr_trans
+            .as_ref()
+            .is_none_or(|trans| trans.borrow().error != 0)
+        {
+            let trans = Transaction::new(Arc::new(self.clone()));
+            jbd_fs.curr_trans = Some(Arc::new(RefCell::new(trans)));
+        }
+        jbd_fs.curr_trans.clone().unwrap()
+    }
+
+    // 日志中还能预留给新句柄的块数：空闲块减去尚未提交的事务最多还要占用的块
+    pub fn log_space_left(&self) -> u32 {
+        self.free.saturating_sub(self.jbd_log_reserved(0, 0))
+    }
+
+    // 尚未提交的事务最多还要占用的日志块数，nblocks 和 nrevokes 是准备追加到运行事务中的块和撤销记录
+    fn jbd_log_reserved(&self, nblocks: u32, nrevokes: u32) -> u32 {
+        let jbd_fs = unsafe { &*self.jbd_fs };
+        let mut reserved = match &jbd_fs.curr_trans {
+            Some(trans) => {
+                let trans = trans.borrow();
+                self.jbd_log_blocks(
+                    trans.outstanding_credits + nblocks,
+                    trans.revoke_root.len() as u32 + nrevokes,
+                )
+            }
+            None => self.jbd_log_blocks(nblocks, nrevokes),
+        };
+        // 正在提交的事务已经分配的块不再计入
+        if let Some(trans) = &jbd_fs.commit_trans {
+            let trans = trans.borrow();
+            reserved += self
+                .jbd_log_blocks(trans.outstanding_credits, trans.revoke_root.len() as u32)
+                .saturating_sub(trans.alloc_blocks as u32);
+        }
+        reserved
+    }
+
+    // 日志空间不足时依次对最老的事务做检查点，直到运行事务放得下 nblocks 个块和 nrevokes 条撤销记录
+    pub fn jbd_log_wait_for_space(&mut self, nblocks: u32, nrevokes: u32) -> Result<(), String> {
+        while self.free < self.jbd_log_reserved(nblocks, nrevokes) {
+            if self.aborted {
+                return Err("Journal has been aborted".to_string());
+            }
+            if self.cp_queue.queue.is_empty() {
+                return Err("No space left in the journal".to_string());
+            }
+            self.jbd_journal_purge_cp_trans(true, true);
+        }
+        Ok(())
+    }
+
+    // 记录 nblocks 个数据块和 nrevokes 条撤销记录最多需要的日志块数，
+    // 包括描述符块、撤销块和提交块
+    pub fn jbd_log_blocks(&self, nblocks: u32, nrevokes: u32) -> u32 {
+        let rec_size = if self.has_feature(JBD_FEATURE_INCOMPAT_64BIT) { 8 } else { 4 };
+        let tags_per_block =
+            ((BLOCK_SIZE - core::mem::size_of::<JbdBhdr>()) / self.jbd_tag_bytes()) as u32;
+        let recs_per_block =
+            ((BLOCK_SIZE - core::mem::size_of::<JbdRevokeHeader>()) / rec_size) as u32;
+
+        nblocks + nblocks.div_ceil(tags_per_block) + nrevokes.div_ceil(recs_per_block) + 1
+    }
+
+    // 运行的事务再预留 nblocks 个日志块就会超过单个事务的上限
+    pub fn jbd_running_trans_full(&self, nblocks: u32) -> bool {
+        let jbd_fs = unsafe { &*self.jbd_fs };
+        match &jbd_fs.curr_trans {
+            Some(trans) => trans.borrow().outstanding_credits + nblocks > self.max_trans_buffers,
+            None => false,
+        }
+    }
+
+    // 是否有事务已被锁定、正在提交
+    pub fn jbd_commit_busy(&self) -> bool {
+        let jbd_fs = unsafe { &*self.jbd_fs };
+        jbd_fs.commit_trans.is_some()
+    }
+
+    // 提交当前运行的事务。事务先被锁定，之后开始的句柄加入新的运行事务；
+    // 已有的句柄全部结束之后才真正写日志
+    pub fn commit(&mut self) -> Result<(), String> {
+        if self.aborted {
+            return Err("Journal has been aborted".to_string());
+        }
+
+        let jbd_fs = unsafe { &mut *self.jbd_fs };
+        let trans = match jbd_fs.curr_trans.clone() {
+            Some(trans) => trans,
+            None => return Ok(()),
+        };
+
+        // 同一时刻只有一个事务在提交
+        if jbd_fs.commit_trans.is_some() {
+            return Err("Previous transaction is still committing".to_string());
+        }
+
+        trans.borrow_mut().state = TransState::Locked;
+        jbd_fs.curr_trans = None;
+        jbd_fs.commit_trans = Some(trans.clone());
+
+        if trans.borrow().updates > 0 {
+            return Ok(());
+        }
+        self.jbd_journal_commit_locked()
+    }
 
+    // 提交已经锁定且所有句柄都已结束的事务。写日志期间事务留在 commit_trans 中，
+    // 它的状态反映提交进行到哪一步
+    pub fn jbd_journal_commit_locked(&mut self) -> Result<(), String> {
+        if self.aborted {
+            return Err("Journal has been aborted".to_string());
+        }
 
+        let jbd_fs = unsafe { &mut *self.jbd_fs };
+        let trans = match jbd_fs.commit_trans.clone() {
+            Some(trans) => trans,
+            None => return Ok(()),
+        };
+
+        // 已被中止或者没有任何内容的事务不写日志，磁盘保持不变
+        let discard = {
+            let trans = trans.borrow();
+            trans.error != 0 || (trans.buf_queue.is_empty() && trans.revoke_root.is_empty())
+        };
+        if discard {
+            jbd_fs.commit_trans = None;
+            return Ok(());
+        }
+
+        let result = self.commit_trans(&trans);
+        jbd_fs.commit_trans = None;
+
+        // 提交中途失败时日志已经不一致，只能中止
+        if let Err(e) = result {
+            jbd_fs.abort(-ENOSPC);
+            return Err(e);
+        }
+        self.jbd_journal_write_sb();
+
+        Ok(())
+    }
 
-    pub fn commit_trans(&mut self, trans: &mut Transaction){
+    pub fn commit_trans(&mut self, trans_ref: &Arc<RefCell<Transaction>>) -> Result<(), String> {
         let bdev = &unsafe { &*self.jbd_fs }.bdev;
-        let last = self.last;
 
-        trans.trans_id = self.alloc_trans_id;
+        {
+            let mut trans = trans_ref.borrow_mut();
+            trans.trans_id = self.alloc_trans_id;
+            trans.data_csum = !0;
+            trans.state = TransState::Flushing;
 
-        // desc block
-        self.write_descriptor_block(trans);
+            // desc block and log copies of the data blocks
+            self.write_descriptor_block(&mut trans)?;
 
-        // revoke block
-        self.write_revoke_block(trans);
+            // revoke block
+            self.write_revoke_block(&mut trans)?;
+        }
+
+        // 提交块之前，描述符块、数据块和撤销块必须已经落盘，
+        // 否则提交块可能先于它所描述的数据到达介质。
+        // 异步提交模式下省去这次刷新，恢复时依靠提交块中的校验和发现不完整的事务
+        let async_commit = self.has_feature(JBD_FEATURE_INCOMPAT_ASYNC_COMMIT);
+        if self.barrier && !async_commit {
+            bdev.flush();
+        }
+
+        let (start_iblock, trans_id, data_cnt) = {
+            let trans = trans_ref.borrow();
+            (trans.start_iblock, trans.trans_id, trans.data_cnt)
+        };
+        if self.cp_queue.queue.is_empty() && data_cnt > 0 {
+            self.jbd_journal_write_sb_tail(start_iblock, trans_id);
+        }
 
         // commit block
-        self.write_commit_block(trans);
+        trans_ref.borrow_mut().state = TransState::Committing;
+        self.write_commit_block(trans_ref)?;
+
+        if self.barrier && async_commit {
+            bdev.flush();
+        }
+
+        let mut trans = trans_ref.borrow_mut();
+        let trans = &mut *trans;
+        trans.state = TransState::Committed;
+
+        // 有数据的事务留在日志中等待检查点；没有数据的事务如果前面也没有
+        // 等待检查点的事务，它占用的日志块可以立即释放
+        self.jbd_journal_track_blocks(trans);
 
-        // Update the journal start and transaction ID based on whether the checkpoint queue is empty
         if self.cp_queue.queue.is_empty() {
-            // If there's data to write, update the journal start to this transaction's start block
             if trans.data_cnt > 0 {
                 self.start = trans.start_iblock;
                 self.trans_id = trans.trans_id;
-                // Add transaction to the checkpoint queue
                 self.cp_queue.queue.push_back(trans.clone());
             } else {
-                // If no data to write, move the start beyond this transaction's allocated blocks
-                self.start = trans.start_iblock + trans.alloc_blocks as u32;
-                self.trans_id = trans.trans_id + 1;
+                self.start = self.last;
+                self.free += trans.alloc_blocks as u32;
+                self.trans_id = trans.trans_id.wrapping_add(1);
             }
-
-            // Write the updated journal superblock to disk
-            // self.write_superblock();
-
         } else {
             // If the checkpoint queue is not empty, just add this transaction
             self.cp_queue.queue.push_back(trans.clone());
         }
 
         // Increment the allocation transaction ID for the next transaction
-        self.alloc_trans_id += 1;
+        self.alloc_trans_id = self.alloc_trans_id.wrapping_add(1);
 
+        Ok(())
     }
 
 
-    pub fn write_descriptor_block(&mut self, trans: &mut Transaction){
-        
-        let bhdr = JbdBhdr {
-            magic: JBD_MAGIC_NUMBER,
-            blocktype: JBD_DESCRIPTOR_BLOCK,
-            sequence: trans.trans_id.to_be(),
-        };
+    // 记录每个块最新的日志副本所在的事务，更早事务中的副本被取代后直接释放
+    fn jbd_journal_track_blocks(&mut self, trans: &Transaction) {
+        for jbd_buf in &trans.buf_queue {
+            let lba = jbd_buf.jbd_lba;
+            if let Some(old_trans_id) = self.block_rec_root.insert(lba, trans.trans_id) {
+                if let Some(old_trans) = self
+                    .cp_queue
+                    .queue
+                    .iter_mut()
+                    .find(|t| t.trans_id == old_trans_id)
+                {
+                    old_trans.jbd_trans_release_block(lba);
+                }
+            }
+        }
+    }
 
-        let desc_iblock = self.jbd_journal_alloc_block(trans);
+    pub fn write_descriptor_block(&mut self, trans: &mut Transaction) -> Result<(), String> {
+        let tag_bytes = self.jbd_tag_bytes();
+        let tags_per_block = (BLOCK_SIZE - core::mem::size_of::<JbdBhdr>()) / tag_bytes;
 
-        // 将描述符块头部写入块缓冲区的开始位置
-        let mut desc_block_data = vec![0u8; BLOCK_SIZE as usize];
-        desc_block_data[0..core::mem::size_of::<JbdBhdr>()].copy_from_slice(&bhdr.to_be_bytes());
+        let bufs: Vec<JbdBuf> = trans.buf_queue.iter().cloned().collect();
 
+        // 每个描述符块之后紧跟它所描述的数据块
+        for chunk in bufs.chunks(tags_per_block) {
+            let bhdr = JbdBhdr {
+                magic: JBD_MAGIC_NUMBER,
+                blocktype: JBD_DESCRIPTOR_BLOCK,
+                sequence: trans.trans_id,
+            };
 
-        // 计算标签开始位置
-        let mut tag_ptr = unsafe{desc_block_data.as_mut_ptr().add(core::mem::size_of::<JbdBhdr>())};
+            let desc_iblock = self.jbd_journal_alloc_block(trans)?;
 
+            // 将描述符块头部写入块缓冲区的开始位置
+            let mut desc_block_data = vec![0u8; BLOCK_SIZE as usize];
+            desc_block_data[0..core::mem::size_of::<JbdBhdr>()].copy_from_slice(&bhdr.to_be_bytes());
 
-        let mut tag_ptr_offset: usize = core::mem::size_of::<JbdBhdr>() + 1;
+            // 标签紧跟在头部之后
+            let mut tag_ptr_offset: usize = core::mem::size_of::<JbdBhdr>();
 
-        // 遍历事务中的所有缓冲区，为每个缓冲区创建标签
-        for (index, jbd_buf) in trans.buf_queue.iter().enumerate() {
+            let mut log_blocks: Vec<(u32, Vec<u8>)> = Vec::new();
 
-            // 标记最后一个缓冲区的标签
-            let is_last_tag = index == trans.buf_queue.len() - 1;
+            // 遍历事务中的所有缓冲区，为每个缓冲区创建标签并写入日志副本
+            for (index, jbd_buf) in chunk.iter().enumerate() {
+                // 标记最后一个缓冲区的标签
+                let is_last_tag = index == chunk.len() - 1;
 
-            let tag_info: TagInfo = TagInfo {
-                block: jbd_buf.block.lb_id,
-                is_escape: false, // 这里应该根据实际情况判断是否需要转义
-                checksum: 0, // 实际应用中应计算校验和
-                last_tag: is_last_tag,
-                ..Default::default()
-            };
+                let mut log_data = jbd_buf.block.data.clone();
+
+                // 数据块以日志魔数开头时需要转义，否则恢复时会被误认为日志元数据块
+                let is_escape = log_data.len() >= 4
+                    && u32::from_be_bytes(log_data[0..4].try_into().unwrap()) == JBD_MAGIC_NUMBER;
+                if is_escape {
+                    log_data[0..4].fill(0);
+                }
 
-            let tag_slice = &mut desc_block_data[tag_ptr_offset..];
-            self.jbd_write_block_tag(tag_slice, &tag_info).expect("Failed to write tag");
+                let tag_info: TagInfo = TagInfo {
+                    block: jbd_buf.block.lb_id,
+                    is_escape,
+                    checksum: 0, // 实际应用中应计算校验和
+                    last_tag: is_last_tag,
+                    ..Default::default()
+                };
 
-            tag_ptr_offset += self.jbd_tag_bytes();
-        }
+                let tag_slice = &mut desc_block_data[tag_ptr_offset..];
+                self.jbd_write_block_tag(tag_slice, &tag_info).expect("Failed to write tag");
 
-        if trans.start_iblock == 0 {
-            
-            // 如果是第一个事务，将描述符块的起始块号写入日志超级块
-            let jbd_fs = unsafe { &mut *self.jbd_fs };
-            jbd_fs.sb.first = desc_iblock;
-            jbd_fs.dirty = true;
-            trans.start_iblock = desc_iblock;
-        }
-        let bdev = &unsafe { &*self.jbd_fs }.bdev;
-        bdev.write_offset(desc_iblock as usize * BLOCK_SIZE as usize, &desc_block_data);
+                tag_ptr_offset += tag_bytes;
 
+                let data_iblock = self.jbd_journal_alloc_block(trans)?;
+                log_blocks.push((data_iblock, log_data));
+            }
 
-        // let block = bdev.read_offset((desc_iblock as usize) * BLOCK_SIZE);
-        // let header = block.as_ptr() as *const JbdBhdr; // Cast data to JbdBhdr
-        // let magic = unsafe { (*header).magic.to_be() };
+            // 事务校验和按日志中的顺序覆盖描述符块和（转义后的）数据块
+            if self.has_trans_csum() {
+                trans.data_csum = jbd_crc32_be(trans.data_csum, &desc_block_data);
+                for (_, log_data) in &log_blocks {
+                    trans.data_csum = jbd_crc32_be(trans.data_csum, log_data);
+                }
+            }
 
-        // if magic != JBD_MAGIC_NUMBER {
-        //     log::info!("Invalid magic number found in journal.");
-        // }
-        // let tag_ptr = unsafe{block.as_ptr().add(core::mem::size_of::<JbdBhdr>())};
-        // let jbdtag = unsafe { &*(tag_ptr as *const JbdBlockTag3) };
+            let bdev = &unsafe { &*self.jbd_fs }.bdev;
+            bdev.write_offset(desc_iblock as usize * BLOCK_SIZE as usize, &desc_block_data);
+            for (data_iblock, log_data) in &log_blocks {
+                bdev.write_offset(*data_iblock as usize * BLOCK_SIZE, log_data);
+            }
+        }
 
+        Ok(())
     }
 
 
-    pub fn write_revoke_block(&mut self, trans: &mut Transaction) {
-        let bhdr = JbdBhdr {
-            magic: JBD_MAGIC_NUMBER,
-            blocktype: JBD_REVOKE_BLOCK,
-            sequence: trans.trans_id.to_be(),
-        };
-    
-        let revoke_iblock = self.jbd_journal_alloc_block(trans);
-    
-        let mut revoke_block_data = vec![0u8; BLOCK_SIZE as usize];
-        revoke_block_data[0..core::mem::size_of::<JbdBhdr>()].copy_from_slice(&bhdr.to_be_bytes());
-    
-
-        let mut offset = core::mem::size_of::<JbdBhdr>();
-        for rec in &trans.revoke_root {
-            let rec_data = rec.1.lba.to_be_bytes();  
-            let rec_slice = &mut revoke_block_data[offset..offset + rec_data.len()];
-            rec_slice.copy_from_slice(&rec_data);
-            offset += rec_data.len();
-        }
-    
-        let bdev = &unsafe { &*self.jbd_fs }.bdev;
-        bdev.write_offset(revoke_iblock as usize * BLOCK_SIZE as usize, &revoke_block_data);
+    pub fn write_revoke_block(&mut self, trans: &mut Transaction) -> Result<(), String> {
+        let rec_size = if self.has_feature(JBD_FEATURE_INCOMPAT_64BIT) { 8 } else { 4 };
+        let hdr_size = core::mem::size_of::<JbdRevokeHeader>();
+        let recs_per_block = (BLOCK_SIZE - hdr_size) / rec_size;
+
+        // 没有撤销记录的事务不写撤销块
+        let lbas: Vec<u32> = trans.revoke_root.keys().cloned().collect();
+        for chunk in lbas.chunks(recs_per_block) {
+            let bhdr = JbdBhdr {
+                magic: JBD_MAGIC_NUMBER,
+                blocktype: JBD_REVOKE_BLOCK,
+                sequence: trans.trans_id,
+            };
+
+            let revoke_iblock = self.jbd_journal_alloc_block(trans)?;
+
+            let mut revoke_block_data = vec![0u8; BLOCK_SIZE];
+            revoke_block_data[0..core::mem::size_of::<JbdBhdr>()].copy_from_slice(&bhdr.to_be_bytes());
+
+            // r_count 是块内已使用的字节数，包括头部
+            let mut offset = hdr_size;
+            for lba in chunk {
+                if rec_size == 8 {
+                    revoke_block_data[offset..offset + 8].copy_from_slice(&(*lba as u64).to_be_bytes());
+                } else {
+                    revoke_block_data[offset..offset + 4].copy_from_slice(&lba.to_be_bytes());
+                }
+                offset += rec_size;
+            }
+            let count_off = core::mem::size_of::<JbdBhdr>();
+            revoke_block_data[count_off..count_off + 4].copy_from_slice(&(offset as u32).to_be_bytes());
+
+            let bdev = &unsafe { &*self.jbd_fs }.bdev;
+            bdev.write_offset(revoke_iblock as usize * BLOCK_SIZE, &revoke_block_data);
+        }
+
+        Ok(())
     }
 
-    pub fn write_commit_block(&mut self, trans: &mut Transaction) {
-        let bhdr = JbdBhdr {
-            magic: JBD_MAGIC_NUMBER,
-            blocktype: JBD_COMMIT_BLOCK,
-            sequence: trans.trans_id.to_be(),
+    pub fn write_commit_block(&mut self, trans_ref: &Arc<RefCell<Transaction>>) -> Result<(), String> {
+        let mut trans = trans_ref.borrow_mut();
+        let mut commit = CommitBlock {
+            header: JbdBhdr {
+                magic: JBD_MAGIC_NUMBER,
+                blocktype: JBD_COMMIT_BLOCK,
+                sequence: trans.trans_id,
+            },
+            chksum_type: 0,
+            chksum_size: 0,
+            padding: [0; 2],
+            chksum: [0; JBD_CHECKSUM_BYTES],
+            commit_sec: 0,
+            commit_nsec: 0,
         };
-    
-        let commit_iblock = self.jbd_journal_alloc_block(trans);
-    
-        let mut commit_block_data = vec![0u8; BLOCK_SIZE as usize];
-        commit_block_data[0..core::mem::size_of::<JbdBhdr>()].copy_from_slice(&bhdr.to_be_bytes());
-    
-    
+
+        // 没有时钟时提交时间保持为 0
+        if let Some(clock) = &self.clock {
+            let (sec, nsec) = clock.now();
+            commit.commit_sec = sec;
+            commit.commit_nsec = nsec;
+        }
+
+        // 提交块携带整个事务的 crc32，恢复时据此判断事务是否完整。
+        // 超级块选择 md5/sha1 时同样写 crc32，内核也只校验 crc32
+        if self.has_trans_csum() {
+            let jbd_fs = unsafe { &*self.jbd_fs };
+            if jbd_fs.sb.checksum_type == JBD_MD5_CHKSUM || jbd_fs.sb.checksum_type == JBD_SHA1_CHKSUM {
+                log::warn!(
+                    "Checksum type {} is not supported, using crc32",
+                    jbd_fs.sb.checksum_type
+                );
+            }
+            commit.chksum_type = JBD_CRC32_CHKSUM;
+            commit.chksum_size = JBD_CRC32_CHKSUM_SIZE;
+            commit.chksum[0] = trans.data_csum;
+        }
+        let async_commit = self.has_feature(JBD_FEATURE_INCOMPAT_ASYNC_COMMIT);
+
+        let commit_iblock = self.jbd_journal_alloc_block(&mut trans)?;
+        // 写设备期间不持有事务的借用，设备回调中仍然可以查看事务状态
+        drop(trans);
+
+        let mut commit_block_data = vec![0u8; BLOCK_SIZE];
+        let commit_bytes = commit.to_be_bytes();
+        commit_block_data[..commit_bytes.len()].copy_from_slice(&commit_bytes);
+
+        // 开启屏障时提交块以 FUA 方式写入，返回时整个事务已经持久化。
+        // 异步提交由调用者在写入提交块之后统一刷新
         let bdev = &unsafe { &*self.jbd_fs }.bdev;
-        bdev.write_offset(commit_iblock as usize * BLOCK_SIZE as usize, &commit_block_data);
+        if self.barrier && !async_commit {
+            bdev.write_offset_fua(commit_iblock as usize * BLOCK_SIZE, &commit_block_data);
+        } else {
+            bdev.write_offset(commit_iblock as usize * BLOCK_SIZE, &commit_block_data);
+        }
+
+        Ok(())
     }
 
     // 分配一个新的块并返回其块号
-    pub fn jbd_journal_alloc_block(&mut self, trans: &mut Transaction) -> u32 {
-        let start_block = self.last + 1;
-        self.last += 1;
-        trans.alloc_blocks += 1;
-        // 确保 last 指针没有超过日志的边界
-        // self.wrap();
-
-        // 检查是否还有足够的空间分配块
-        if self.last == self.start {
-            // 没有空间时，尝试清理已提交的事务
+    pub fn jbd_journal_alloc_block(&mut self, trans: &mut Transaction) -> Result<u32, String> {
+        // 预留机制保证提交时一般不会缺少空间；缺少时先对最老的事务做检查点
+        while self.free == 0 && !self.cp_queue.queue.is_empty() && !self.aborted {
             self.jbd_journal_purge_cp_trans(true, true);
-            assert!(self.last != self.start, "No space left in the journal");
         }
+        if self.free == 0 {
+            return Err("No space left in the journal".to_string());
+        }
+
+        let iblock = self.last;
+        self.last = self.wrap(self.last + 1);
+        self.free -= 1;
 
-        start_block
+        // 事务从它分配的第一个块开始
+        if trans.alloc_blocks == 0 {
+            trans.start_iblock = iblock;
+        }
+        trans.alloc_blocks += 1;
+
+        Ok(iblock)
     }
 
-    pub fn jbd_journal_purge_cp_trans(&mut self, flush: bool, once: bool) {
-        while let Some(trans) = self.cp_queue.queue.front().cloned() {
-            if trans.data_cnt == 0 || (flush && trans.data_cnt == trans.written_cnt) {
-                // 更新日志开始位置
-                self.start = trans.start_iblock + trans.alloc_blocks as u32;
-                // self.wrap();
+    // 日志是一个环，越过 maxlen 的块号绕回到 first
+    fn wrap(&self, iblock: u32) -> u32 {
+        let jbd_fs = unsafe { &*self.jbd_fs };
+        let maxlen = jbd_fs.sb.maxlen.to_be();
+        let first = jbd_fs.sb.first.to_be();
+        if iblock >= maxlen {
+            iblock - (maxlen - first)
+        } else {
+            iblock
+        }
+    }
 
-                // 更新事务ID
-                self.trans_id = trans.trans_id + 1;
+    pub fn jbd_journal_purge_cp_trans(&mut self, flush: bool, once: bool) {
+        // 中止后的日志是只读的，不再写回块也不推进日志起点
+        if self.aborted {
+            return;
+        }
 
-                // 从检查点队列中移除事务
-                self.cp_queue.queue.pop_front();
+        let mut purged = false;
 
-                // 如果需要，这里可以添加更多清理事务的逻辑
-                // 比如释放事务占用的资源等
+        while let Some(trans) = self.cp_queue.queue.front() {
+            if trans.data_cnt == 0 || (flush && trans.data_cnt == trans.written_cnt) {
+                // 从检查点队列中移除事务，释放它占用的日志块
+                let mut trans = self.cp_queue.queue.pop_front().unwrap();
+                self.start = self.wrap(trans.start_iblock + trans.alloc_blocks as u32);
+                self.free += trans.alloc_blocks as u32;
+                self.trans_id = trans.trans_id.wrapping_add(1);
+                trans.state = TransState::Checkpointed;
+                purged = true;
 
                 // 如果只处理一次，退出循环
                 if once {
@@ -227,17 +566,33 @@ impl JbdJournal {
             } else if !flush {
                 // 如果不刷新数据，更新日志开始位置并退出循环
                 self.start = trans.start_iblock;
-                // self.wrap();
                 self.trans_id = trans.trans_id;
                 break;
             } else {
-                // 如果需要刷新数据，调用 jbd_journal_flush_trans 处理事务
-                self.jbd_journal_flush_trans(&trans);
+                // 先把事务中的块写回原位置，下一轮循环再移除它
+                let mut trans = self.cp_queue.queue.pop_front().unwrap();
+                self.jbd_journal_flush_trans(&mut trans);
+                self.cp_queue.queue.push_front(trans);
             }
         }
+
+        // 检查点写回的块已经落盘，推进超级块中的日志起点
+        if purged {
+            self.jbd_journal_write_sb();
+        }
     }
 
-    pub fn jbd_journal_flush_trans(&mut self, trans: &Transaction) {
+    // 把事务记录的块写回它们在文件系统中的位置并刷新设备；
+    // 已被更新的事务取代的块跳过，由那个事务检查点时写回
+    pub fn jbd_journal_flush_trans(&mut self, trans: &mut Transaction) {
+        let bdev = &unsafe { &*self.jbd_fs }.bdev;
+        for jbd_buf in trans.buf_queue.iter_mut().filter(|buf| buf.dirty) {
+            bdev.write_offset(jbd_buf.jbd_lba as usize * BLOCK_SIZE, &jbd_buf.block.data);
+            jbd_buf.dirty = false;
+            self.block_rec_root.remove(jbd_buf.jbd_lba, trans.trans_id);
+        }
+        bdev.flush();
+        trans.written_cnt = trans.data_cnt;
     }
 
     pub fn jbd_write_block_tag(
@@ -252,52 +607,63 @@ impl JbdJournal {
             return Err("Buffer size is too small".to_string());
         }
 
-        if self.has_feature(JBD_FEATURE_INCOMPAT_CSUM_V3 as u32) {
-            // 使用 JbdBlockTag3 结构
-            if tag_info.uuid_exist && tag.len() < tag_bytes + UUID_SIZE {
-                return Err("Buffer size is too small for UUID".to_string());
-            }
+        if tag_info.uuid_exist && tag.len() < tag_bytes + UUID_SIZE {
+            return Err("Buffer size is too small for UUID".to_string());
+        }
 
-            let mut tag3 = JbdBlockTag3 {
-                blocknr: tag_info.block as u32, 
-                checksum: tag_info.checksum as u16, 
-                flags: 0,                       
-                blocknr_high: (tag_info.block >> 32) as u32,
-            };
+        let mut tag3 = JbdBlockTag3 {
+            blocknr: tag_info.block as u32,
+            checksum: tag_info.checksum as u16,
+            flags: 0,
+            blocknr_high: (tag_info.block >> 32) as u32,
+        };
 
-            // 设置标志位
-            if tag_info.is_escape {
-                tag3.flags |= JBD_FLAG_ESCAPE as u16;
-            }
-            if tag_info.last_tag {
-                tag3.flags |= JBD_FLAG_LAST_TAG as u16;
-            }
-            if !tag_info.uuid_exist {
-                tag3.flags |= JBD_FLAG_SAME_UUID as u16;
-            }
+        // 设置标志位
+        if tag_info.is_escape {
+            tag3.flags |= JBD_FLAG_ESCAPE as u16;
+        }
+        if tag_info.last_tag {
+            tag3.flags |= JBD_FLAG_LAST_TAG;
+        }
+        if !tag_info.uuid_exist {
+            tag3.flags |= JBD_FLAG_SAME_UUID as u16;
+        }
 
-            let tag3_bytes: &[u8] = unsafe { any_as_u8_slice(&tag3) };
-            tag[..tag3_bytes.len()].copy_from_slice(tag3_bytes);
+        // 没有 64BIT 特性时标签不包含 blocknr_high
+        let tag3_bytes = tag3.to_be_bytes();
+        tag[..tag_bytes].copy_from_slice(&tag3_bytes[..tag_bytes]);
 
-            // 如果存在 UUID，将其追加到标签之后
-            if tag_info.uuid_exist {
-                let uuid_start = tag_bytes;
-                tag[uuid_start..uuid_start + UUID_SIZE].copy_from_slice(&tag_info.uuid);
-            }
-        } else {
-            // 使用 JbdBlockTag 结构
-            // 类似地处理 JbdBlockTag，如上所示
+        // 如果存在 UUID，将其追加到标签之后
+        if tag_info.uuid_exist {
+            let uuid_start = tag_bytes;
+            tag[uuid_start..uuid_start + UUID_SIZE].copy_from_slice(&tag_info.uuid);
         }
 
         Ok(())
     }
 
     pub fn has_feature(&self, feature: u32) -> bool {
-        true
+        let jbd_fs = unsafe { &*self.jbd_fs };
+        jbd_fs.sb.feature_incompat.to_be() & feature != 0
+    }
+
+    pub fn has_compat_feature(&self, feature: u32) -> bool {
+        let jbd_fs = unsafe { &*self.jbd_fs };
+        jbd_fs.sb.feature_compat.to_be() & feature != 0
+    }
+
+    // 提交块中是否记录整个事务的 crc32（v1 校验和，异步提交同样依赖它）
+    pub fn has_trans_csum(&self) -> bool {
+        self.has_compat_feature(JBD_FEATURE_COMPAT_CHECKSUM)
+            || self.has_feature(JBD_FEATURE_INCOMPAT_ASYNC_COMMIT)
     }
 
     fn jbd_tag_bytes(&self) -> usize {
-        core::mem::size_of::<JbdBlockTag3>()
+        if self.has_feature(JBD_FEATURE_INCOMPAT_64BIT) {
+            core::mem::size_of::<JbdBlockTag3>()
+        } else {
+            core::mem::size_of::<JbdBlockTag3>() - 4
+        }
     }
 }
 
@@ -327,4 +693,44 @@ impl JbdBhdr {
         bytes.extend_from_slice(&self.sequence.to_be_bytes());
         bytes
     }
-}
\ No newline at end of file
+}
+
+impl CommitBlock {
+    pub fn from_be_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < core::mem::size_of::<Self>() {
+            return Err("Byte slice is too short".into());
+        }
+
+        let header = JbdBhdr::from_be_bytes(bytes)?;
+        let mut chksum = [0u32; JBD_CHECKSUM_BYTES];
+        for (i, c) in chksum.iter_mut().enumerate() {
+            let off = 16 + i * 4;
+            *c = u32::from_be_bytes(bytes[off..off + 4].try_into().unwrap());
+        }
+        let commit_sec = u64::from_be_bytes(bytes[48..56].try_into().unwrap());
+        let commit_nsec = u32::from_be_bytes(bytes[56..60].try_into().unwrap());
+
+        Ok(Self {
+            header,
+            chksum_type: bytes[12],
+            chksum_size: bytes[13],
+            padding: [bytes[14], bytes[15]],
+            chksum,
+            commit_sec,
+            commit_nsec,
+        })
+    }
+
+    pub fn to_be_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header.to_be_bytes();
+        bytes.push(self.chksum_type);
+        bytes.push(self.chksum_size);
+        bytes.extend_from_slice(&self.padding);
+        for c in &self.chksum {
+            bytes.extend_from_slice(&c.to_be_bytes());
+        }
+        bytes.extend_from_slice(&self.commit_sec.to_be_bytes());
+        bytes.extend_from_slice(&self.commit_nsec.to_be_bytes());
+        bytes
+    }
+}
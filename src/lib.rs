@@ -1,5 +1,7 @@
 pub mod defs;
+pub mod handle;
 pub mod consts;
+pub mod crc;
 pub mod prelude;
 pub mod jbd;
 pub mod journal;
@@ -7,6 +9,7 @@ pub mod transaction;
 
 pub use defs::*;
 pub use consts::*;
+pub use crc::*;
 pub use prelude::*;
 pub use jbd::*;
 pub use journal::*;
@@ -1,11 +1,14 @@
 pub mod consts;
+pub mod crc;
 pub mod defs;
+pub mod handle;
 pub mod jbd;
 pub mod journal;
 pub mod prelude;
 pub mod transaction;
 
 pub use consts::*;
+pub use crc::*;
 pub use defs::*;
 pub use jbd::*;
 pub use journal::*;
@@ -129,9 +132,38 @@ impl Ext4Fs for Ext4{
         let r = file.read_exact(&mut buf);
         buf
     }
+
+    fn clear_needs_recovery(&self) {
+        let offset = EXT4_SUPERBLOCK_OFFSET + EXT4_FEATURE_INCOMPAT_OFFSET;
+        use std::fs::OpenOptions;
+        use std::io::{Read, Seek, Write};
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("ex4.img")
+            .unwrap();
+        let mut buf = [0u8; 4];
+        let r = file.seek(std::io::SeekFrom::Start(offset as u64));
+        let r = file.read_exact(&mut buf);
+        let incompat = u32::from_le_bytes(buf) & !EXT4_FEATURE_INCOMPAT_RECOVER;
+        let r = file.seek(std::io::SeekFrom::Start(offset as u64));
+        let r = file.write_all(&incompat.to_le_bytes());
+        let r = file.sync_all();
+    }
 }
 
 
+#[derive(Debug)]
+pub struct SystemClock;
+
+impl JbdClock for SystemClock {
+    fn now(&self) -> (u64, u32) {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap();
+        (now.as_secs(), now.subsec_nanos())
+    }
+}
 
 
 fn main() {
@@ -149,10 +181,13 @@ fn main() {
         ext4fs: ext4,
         dirty: false,
         curr_trans: None,
+        commit_trans: None,
     };
 
+    jbd_fs.set_clock(Arc::new(SystemClock));
+
     // journal start at mount
-    jbd_fs.journal_start();
+    jbd_fs.journal_start().unwrap();
 
     jbd_fs.trans_start();
 
@@ -166,11 +201,15 @@ fn main() {
     jbd_fs.bdev.write_offset((block.lb_id as usize) * BLOCK_SIZE, &block.data);
 
     // write a transaction
-    jbd_fs.write_trans(block);
+    jbd_fs.write_trans(block).unwrap();
 
     // commit the transaction
-    jbd_fs.trans_stop();
+    jbd_fs.trans_stop().unwrap();
 
     log::info!("recovering...");
-    let r: Result<(), String> = jbd_fs.recover();
+    let report = jbd_fs.recover().unwrap();
+    log::info!("replayed blocks: {:x?}", report.replayed_blocks);
+
+    // unmount
+    jbd_fs.journal_stop().unwrap();
 }
@@ -7,20 +7,50 @@ impl Transaction {
     pub fn new(journal: Arc<JbdJournal>) -> Transaction {
         Transaction {
             trans_id: 0,
+            state: TransState::Running,
             start_iblock: 0,
             alloc_blocks: 0,
             data_cnt: 0,
             data_csum: 0,
             written_cnt: 0,
             error: 0,
+            updates: 0,
+            outstanding_credits: 0,
             journal: journal,
             buf_queue: VecDeque::new(),
             revoke_root: BTreeMap::new(),
-            tbrec_list: Vec::new(),
+            tbrec_root: BTreeMap::new(),
+        }
+    }
+
+    // 本事务是否已经记录了这个块
+    pub fn jbd_trans_has_block(&self, lba: u32) -> bool {
+        self.tbrec_root.contains_key(&lba)
+    }
+
+    // 本事务中该块的内容，已被释放的副本不返回
+    pub fn jbd_trans_get_block(&self, lba: u32) -> Option<&Vec<u8>> {
+        let rec = self.tbrec_root.get(&lba)?;
+        let buf = &self.buf_queue[rec.buf_idx];
+        if buf.dirty {
+            Some(&buf.block.data)
+        } else {
+            None
         }
     }
 
     pub fn jbd_trans_set_block_dirty(&mut self, block: Ext4Block) {
+        // 撤销之后重新写入的块取消本事务中的撤销记录，否则恢复时会跳过新写入的副本
+        self.revoke_root.remove(&(block.lb_id as u32));
+
+        // 同一个块在一个事务中只写一次日志，后写入的内容替换之前的缓冲区
+        if let Some(rec) = self.tbrec_root.get(&(block.lb_id as u32)) {
+            let buf = &mut self.buf_queue[rec.buf_idx];
+            buf.buffer.data = block.data.clone();
+            buf.block = block;
+            return;
+        }
+
         let buffer = Buffer{
             block_num: block.lb_id as u32, 
             data: block.data.clone(),  
@@ -37,6 +67,14 @@ impl Transaction {
             dirty: true,
         };
 
+        let lba = buf.jbd_lba;
+        self.tbrec_root.insert(
+            lba,
+            JbdBlockRec {
+                lba,
+                buf_idx: self.buf_queue.len(),
+            },
+        );
         self.buf_queue.push_back(buf);
 
         self.data_cnt += 1;
@@ -44,4 +82,51 @@ impl Transaction {
 
         log::debug!("buf queue {:x?}", self.buf_queue);
     }
-}
\ No newline at end of file
+
+    // 更新的事务已经记录了这个块，本事务中的副本不必再写回，释放它的数据
+    pub fn jbd_trans_release_block(&mut self, lba: u32) {
+        if let Some(rec) = self.tbrec_root.get(&lba) {
+            let buf = &mut self.buf_queue[rec.buf_idx];
+            if buf.dirty {
+                buf.dirty = false;
+                buf.block.data = Vec::new();
+                buf.buffer.data = Vec::new();
+                self.written_cnt += 1;
+            }
+        }
+    }
+
+    // 丢弃事务中的所有缓冲区和撤销记录，归还它们占用的日志块，磁盘保持不变；
+    // 已经开始写日志的事务不能中止。中止后事务记录错误，不再接受修改，也不会提交
+    pub fn abort(&mut self) -> Result<(), String> {
+        if self.state != TransState::Running && self.state != TransState::Locked {
+            return Err("Transaction is already committing or committed".to_string());
+        }
+
+        self.error = -ECANCELED;
+        self.outstanding_credits -= self.data_cnt as u32;
+        self.buf_queue.clear();
+        self.revoke_root.clear();
+        self.tbrec_root.clear();
+        self.data_cnt = 0;
+
+        Ok(())
+    }
+
+    // 撤销一个块：恢复时跳过本事务及更早事务中该块的日志副本。
+    // 本事务已经写入的副本一并丢弃并归还它的日志块（jbd2_journal_forget），
+    // 否则检查点仍会把它写回原位置，恢复时却被撤销记录跳过
+    pub fn jbd_trans_revoke_block(&mut self, lba: u32) {
+        if let Some(rec) = self.tbrec_root.remove(&lba) {
+            self.buf_queue.remove(rec.buf_idx);
+            for other in self.tbrec_root.values_mut() {
+                if other.buf_idx > rec.buf_idx {
+                    other.buf_idx -= 1;
+                }
+            }
+            self.data_cnt -= 1;
+            self.outstanding_credits -= 1;
+        }
+        self.revoke_root.insert(lba, JbdRevokeRec { lba });
+    }
+}
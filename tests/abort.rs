@@ -0,0 +1,110 @@
+mod common;
+
+use common::*;
+use jbd2_rs::*;
+
+#[test]
+fn handle_abort_discards_the_whole_transaction() {
+    let dev = mkdev(1, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    let mut a = fs.journal.start(4).unwrap();
+    let mut b = fs.journal.start(4).unwrap();
+    b.write(block(7, 7)).unwrap();
+    a.write(block(8, 8)).unwrap();
+    a.abort().unwrap();
+
+    // 同一事务中的其他句柄不能再写入，结束时报告事务已被中止
+    assert!(b.write(block(9, 9)).is_err());
+    assert!(b.stop().is_err());
+
+    // 新的句柄加入新的运行事务
+    let mut c = fs.journal.start(4).unwrap();
+    c.write(block(10, 10)).unwrap();
+    c.stop().unwrap();
+    fs.trans_stop().unwrap();
+
+    let report = mkfs(dev.snapshot()).recover().unwrap();
+    assert_eq!(report.trans_cnt, 1);
+    assert_eq!(report.replayed_blocks, vec![10]);
+}
+
+#[test]
+fn writes_after_transaction_abort_go_to_a_new_transaction() {
+    let dev = mkdev(1, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    fs.write_trans(block(5, 5)).unwrap();
+    let aborted = fs.curr_trans.clone().unwrap();
+    aborted.borrow_mut().abort().unwrap();
+
+    fs.write_trans(block(6, 6)).unwrap();
+    assert!(!aborted.borrow().jbd_trans_has_block(6));
+    fs.trans_stop().unwrap();
+
+    // 只有新事务中的块被提交
+    let report = mkfs(dev.snapshot()).recover().unwrap();
+    assert_eq!(report.trans_cnt, 1);
+    assert_eq!(report.replayed_blocks, vec![6]);
+}
+
+#[test]
+fn aborted_transaction_is_never_committed() {
+    let dev = mkdev(1, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    // 事务锁定之后、最后一个句柄结束之前被中止
+    let mut h = fs.journal.start(1).unwrap();
+    h.write(block(5, 5)).unwrap();
+    fs.trans_stop().unwrap();
+    let aborted = h.trans.clone();
+    aborted.borrow_mut().abort().unwrap();
+    assert!(h.stop().is_err());
+
+    assert!(fs.commit_trans.is_none());
+    assert_eq!(aborted.borrow().state, TransState::Locked);
+    assert_eq!(fs.journal.alloc_trans_id, 1);
+    assert_eq!(fs.sb.start, 0);
+}
+
+#[test]
+fn restart_reports_aborted_transaction() {
+    let mut fs = mkfs(mkdev(1, 0));
+    fs.journal_start().unwrap();
+
+    let mut a = fs.journal.start(1).unwrap();
+    let b = fs.journal.start(1).unwrap();
+    a.write(block(5, 5)).unwrap();
+    b.abort().unwrap();
+    assert!(a.restart(1).is_err());
+
+    // 两个句柄都已结束，事务可以继续使用
+    let h = fs.journal.start(1).unwrap();
+    h.stop().unwrap();
+}
+
+
+#[test]
+fn journal_abort_is_recorded_in_superblock() {
+    let dev = mkdev(1, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+    fs.write_trans(block(2, 1)).unwrap();
+    fs.trans_stop().unwrap();
+
+    fs.abort(-5);
+    assert!(fs.is_aborted());
+    assert!(fs.write_trans(block(3, 1)).is_err());
+    assert!(fs.journal.start(1).is_err());
+
+    // 错误码在重新挂载时仍然可见，清除之后才能再次使用日志
+    let mut fs = mkfs(dev.snapshot());
+    assert_eq!(fs.errno(), -5);
+    assert!(fs.journal_start().is_err());
+    fs.clear_err();
+    fs.journal_start().unwrap();
+}
+
@@ -0,0 +1,53 @@
+mod common;
+
+use common::*;
+use jbd2_rs::*;
+
+#[test]
+fn superseded_copy_is_released_and_not_checkpointed() {
+    let dev = mkdev(1, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    fs.write_trans(block(5, 0x11)).unwrap();
+    fs.write_trans(block(6, 0x11)).unwrap();
+    fs.trans_stop().unwrap();
+    fs.write_trans(block(5, 0x22)).unwrap();
+    fs.trans_stop().unwrap();
+
+    // 块 5 最新的副本在事务 2 中，事务 1 中的旧副本已被释放
+    assert_eq!(fs.journal.block_rec_root.get(5).unwrap().trans_id, 2);
+    assert_eq!(fs.journal.block_rec_root.get(6).unwrap().trans_id, 1);
+    let first = &fs.journal.cp_queue.queue[0];
+    assert_eq!((first.data_cnt, first.written_cnt), (2, 1));
+
+    // 事务 1 的检查点只写回块 6，块 5 不会先被写成旧内容
+    fs.journal.jbd_journal_purge_cp_trans(true, true);
+    assert_eq!(dev.block(5), vec![0; BLOCK_SIZE]);
+    assert_eq!(dev.block(6), vec![0x11; BLOCK_SIZE]);
+    assert!(fs.journal.block_rec_root.get(6).is_none());
+
+    fs.journal.jbd_journal_purge_cp_trans(true, true);
+    assert_eq!(dev.block(5), vec![0x22; BLOCK_SIZE]);
+    assert!(fs.journal.cp_queue.queue.is_empty());
+    assert!(fs.journal.block_rec_root.records.is_empty());
+}
+
+#[test]
+fn fully_superseded_transaction_is_dropped_without_writes() {
+    let dev = mkdev(1, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    fs.write_trans(block(5, 0x11)).unwrap();
+    fs.trans_stop().unwrap();
+    fs.write_trans(block(5, 0x22)).unwrap();
+    fs.trans_stop().unwrap();
+
+    let flushes = *dev.flushes.lock().unwrap();
+    fs.journal.jbd_journal_purge_cp_trans(true, true);
+    assert_eq!(fs.journal.cp_queue.queue.len(), 1);
+    assert_eq!(dev.block(5), vec![0; BLOCK_SIZE]);
+    // 只有推进日志起点的超级块写入，没有写回数据后的刷新
+    assert_eq!(*dev.flushes.lock().unwrap(), flushes);
+}
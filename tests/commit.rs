@@ -0,0 +1,105 @@
+mod common;
+
+use common::*;
+use jbd2_rs::*;
+use std::sync::Arc;
+
+#[test]
+fn first_commit_survives_crash_before_superblock_update() {
+    let dev = mkdev(1, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    fs.write_trans(block(5, 0x11)).unwrap();
+    fs.trans_stop().unwrap();
+
+    // 提交块刚落盘、提交之后的超级块更新还没有写入时掉电
+    let mut fs = mkfs(dev.fua_snapshot());
+    let report = fs.recover().unwrap();
+    assert_eq!(report.trans_cnt, 1);
+    assert_eq!(fs.read_block(5), vec![0x11; BLOCK_SIZE]);
+}
+
+#[test]
+fn recovery_reports_commit_times() {
+    let dev = mkdev(1, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    fs.set_clock(Arc::new(FixedClock(1000, 5)));
+    fs.write_trans(block(5, 0x11)).unwrap();
+    fs.trans_stop().unwrap();
+    fs.set_clock(Arc::new(FixedClock(2000, 7)));
+    fs.write_trans(block(6, 0x22)).unwrap();
+    fs.trans_stop().unwrap();
+
+    let fs = mkfs(dev.snapshot());
+    let report = fs.recover_dry_run().unwrap();
+    assert_eq!(
+        report.commit_times,
+        vec![
+            CommitTime { trans_id: 1, commit_sec: 1000, commit_nsec: 5 },
+            CommitTime { trans_id: 2, commit_sec: 2000, commit_nsec: 7 },
+        ]
+    );
+    assert_eq!((report.last_commit_sec, report.last_commit_nsec), (2000, 7));
+
+    // 只恢复到第一个事务时报告它的提交时间
+    let fs = mkfs(dev.snapshot());
+    let report = fs.recover_until(1).unwrap();
+    assert_eq!(report.commit_times.len(), 1);
+    assert_eq!((report.last_commit_sec, report.last_commit_nsec), (1000, 5));
+}
+
+// 提交第二个事务（超级块已经指向日志）期间设备收到的刷新次数
+fn flushes_for_second_commit(incompat: u32, barrier: bool) -> u32 {
+    let dev = mkdev(1, incompat);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+    fs.set_barrier(barrier);
+
+    fs.write_trans(block(5, 0x11)).unwrap();
+    fs.trans_stop().unwrap();
+    let before = *dev.flushes.lock().unwrap();
+    fs.write_trans(block(6, 0x22)).unwrap();
+    fs.trans_stop().unwrap();
+    let after = *dev.flushes.lock().unwrap();
+    after - before
+}
+
+#[test]
+fn commit_flush_counts() {
+    // 同步提交：提交块之前刷新一次，提交块 FUA 写入再刷新一次
+    assert_eq!(flushes_for_second_commit(0, true), 2);
+    // 异步提交：只在提交块之后刷新一次
+    assert_eq!(flushes_for_second_commit(JBD_FEATURE_INCOMPAT_ASYNC_COMMIT, true), 1);
+    assert_eq!(flushes_for_second_commit(0, false), 0);
+    assert_eq!(flushes_for_second_commit(JBD_FEATURE_INCOMPAT_ASYNC_COMMIT, false), 0);
+}
+
+#[test]
+fn async_commit_is_recovered_and_verified() {
+    let dev = mkdev(1, JBD_FEATURE_INCOMPAT_ASYNC_COMMIT);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    fs.write_trans(block(5, 0x11)).unwrap();
+    fs.trans_stop().unwrap();
+    fs.write_trans(block(6, 0x22)).unwrap();
+    fs.trans_stop().unwrap();
+
+    let report = mkfs(dev.snapshot()).recover().unwrap();
+    assert_eq!(report.trans_cnt, 2);
+    assert_eq!(report.replayed_blocks, vec![5, 6]);
+
+    // 没有刷新屏障时数据块可能没有落盘，提交块中的校验和发现这种不完整的事务
+    let crash = dev.snapshot();
+    let report = mkfs(crash.clone()).recover_dry_run().unwrap();
+    let copy = report.planned_writes.iter().find(|w| w.block == 6).unwrap();
+    crash.write_offset(copy.journal_block as usize * BLOCK_SIZE, &[0; 16]);
+
+    let report = mkfs(crash.clone()).recover().unwrap();
+    assert_eq!(report.trans_cnt, 1);
+    assert_eq!(report.csum_failures, 1);
+    assert_eq!(crash.block(6), vec![0; BLOCK_SIZE]);
+}
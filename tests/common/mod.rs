@@ -0,0 +1,145 @@
+#![allow(dead_code)]
+
+use jbd2_rs::*;
+use std::sync::{Arc, Mutex};
+
+pub const JOURNAL_FIRST: u32 = 100;
+pub const JOURNAL_MAXLEN: u32 = 164;
+
+const DEVICE_SIZE: usize = 2 << 20;
+const JBD_SB_OFFSET: usize = 0x20000;
+
+// 内存中的块设备，记录读请求和刷新的次数。snapshot 出来的副本模拟断电时介质上的内容，
+// 每次 FUA 写入（提交块）之后的内容保存在 fua_snapshot 中
+#[derive(Debug)]
+pub struct MemDevice {
+    pub data: Mutex<Vec<u8>>,
+    pub reads: Mutex<u32>,
+    pub flushes: Mutex<u32>,
+    pub fua_snapshot: Mutex<Option<Vec<u8>>>,
+}
+
+impl MemDevice {
+    pub fn snapshot(&self) -> Arc<MemDevice> {
+        Arc::new(MemDevice {
+            data: Mutex::new(self.data.lock().unwrap().clone()),
+            reads: Mutex::new(0),
+            flushes: Mutex::new(0),
+            fua_snapshot: Mutex::new(None),
+        })
+    }
+
+    // 最近一次 FUA 写入刚完成时介质上的内容
+    pub fn fua_snapshot(&self) -> Arc<MemDevice> {
+        Arc::new(MemDevice {
+            data: Mutex::new(self.fua_snapshot.lock().unwrap().clone().unwrap()),
+            reads: Mutex::new(0),
+            flushes: Mutex::new(0),
+            fua_snapshot: Mutex::new(None),
+        })
+    }
+
+    pub fn block(&self, lba: u32) -> Vec<u8> {
+        self.read_offset(lba as usize * BLOCK_SIZE)
+    }
+}
+
+impl BlockDevice for MemDevice {
+    fn read_offset(&self, offset: usize) -> Vec<u8> {
+        *self.reads.lock().unwrap() += 1;
+        self.data.lock().unwrap()[offset..offset + BLOCK_SIZE].to_vec()
+    }
+
+    fn read_blocks(&self, offset: usize, count: usize) -> Vec<u8> {
+        *self.reads.lock().unwrap() += 1;
+        self.data.lock().unwrap()[offset..offset + count * BLOCK_SIZE].to_vec()
+    }
+
+    fn write_offset(&self, offset: usize, data: &[u8]) {
+        self.data.lock().unwrap()[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    fn flush(&self) {
+        *self.flushes.lock().unwrap() += 1;
+    }
+
+    fn write_offset_fua(&self, offset: usize, data: &[u8]) {
+        self.write_offset(offset, data);
+        self.flush();
+        *self.fua_snapshot.lock().unwrap() = Some(self.data.lock().unwrap().clone());
+    }
+}
+
+#[derive(Debug)]
+pub struct NoExt4;
+
+impl Ext4Fs for NoExt4 {
+    fn get_journal_block(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    fn get_superblock(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    fn clear_needs_recovery(&self) {}
+}
+
+#[derive(Debug)]
+pub struct FixedClock(pub u64, pub u32);
+
+impl JbdClock for FixedClock {
+    fn now(&self) -> (u64, u32) {
+        (self.0, self.1)
+    }
+}
+
+// 日志位于 [JOURNAL_FIRST, JOURNAL_MAXLEN) 的 v2 日志设备，日志为空
+pub fn mkdev(sequence: u32, incompat: u32) -> Arc<MemDevice> {
+    mkdev_with(JOURNAL_MAXLEN, sequence, 0, incompat)
+}
+
+pub fn mkdev_with(maxlen: u32, sequence: u32, compat: u32, incompat: u32) -> Arc<MemDevice> {
+    let mut data = vec![0u8; DEVICE_SIZE];
+    let fields = [
+        (0, JBD_MAGIC_NUMBER),
+        (4, JBD_SUPERBLOCK_V2),
+        (12, BLOCK_SIZE as u32),
+        (16, maxlen),
+        (20, JOURNAL_FIRST),
+        (24, sequence),
+        (28, 0),
+        (36, compat),
+        (40, incompat),
+    ];
+    for (offset, value) in fields {
+        let offset = JBD_SB_OFFSET + offset;
+        data[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+    }
+    Arc::new(MemDevice {
+        data: Mutex::new(data),
+        reads: Mutex::new(0),
+        flushes: Mutex::new(0),
+        fua_snapshot: Mutex::new(None),
+    })
+}
+
+pub fn mkfs(dev: Arc<MemDevice>) -> Box<JbdFs> {
+    let sb = JbdSb::try_from(dev.read_offset(JBD_SB_OFFSET)).unwrap();
+    Box::new(JbdFs {
+        sb,
+        journal: JbdJournal::new(),
+        bdev: dev,
+        ext4fs: Arc::new(NoExt4),
+        dirty: false,
+        curr_trans: None,
+        commit_trans: None,
+    })
+}
+
+pub fn block(lba: u32, fill: u8) -> Ext4Block {
+    Ext4Block {
+        lb_id: lba as u64,
+        data: vec![fill; BLOCK_SIZE],
+    }
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use common::*;
+use jbd2_rs::*;
+use std::sync::Arc;
+
+#[test]
+fn handle_reserves_and_returns_credits() {
+    let dev = mkdev(1, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    let mut h = fs.journal.start(2).unwrap();
+    assert_eq!(h.buffer_credits, 2);
+    assert_eq!(h.trans.borrow().outstanding_credits, 2);
+    assert_eq!(h.trans.borrow().updates, 1);
+
+    h.write(block(5, 1)).unwrap();
+    h.write(block(6, 1)).unwrap();
+    assert_eq!(h.buffer_credits, 0);
+    assert!(h.write(block(7, 1)).is_err());
+
+    let trans = h.trans.clone();
+    let mut h2 = fs.journal.start(3).unwrap();
+    h2.write(block(8, 1)).unwrap();
+    assert_eq!(trans.borrow().outstanding_credits, 5);
+
+    // 结束句柄时归还没有用完的日志块
+    h2.stop().unwrap();
+    assert_eq!(trans.borrow().outstanding_credits, 3);
+    h.stop().unwrap();
+    assert_eq!(trans.borrow().updates, 0);
+    assert_eq!(trans.borrow().data_cnt, 3);
+}
+
+#[test]
+fn extend_adds_credits_up_to_the_limit() {
+    let dev = mkdev(1, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+    let max = fs.journal.max_trans_buffers;
+
+    let mut h = fs.journal.start(1).unwrap();
+    h.write(block(5, 1)).unwrap();
+    h.extend(2).unwrap();
+    assert_eq!(h.buffer_credits, 2);
+    assert_eq!(h.trans.borrow().outstanding_credits, 3);
+    h.write(block(6, 1)).unwrap();
+
+    assert!(h.extend(max).is_err());
+    assert_eq!(h.buffer_credits, 1);
+    h.stop().unwrap();
+}
+
+#[test]
+fn restart_moves_handle_to_a_new_transaction() {
+    let dev = mkdev(1, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    let mut h = fs.journal.start(1).unwrap();
+    h.write(block(5, 1)).unwrap();
+    let old = h.trans.clone();
+
+    let mut h = h.restart(1).unwrap();
+    assert!(!Arc::ptr_eq(&old, &h.trans));
+    assert_eq!(old.borrow().state, TransState::Committed);
+    h.write(block(6, 2)).unwrap();
+    h.stop().unwrap();
+    fs.trans_stop().unwrap();
+
+    let report = mkfs(dev.snapshot()).recover().unwrap();
+    assert_eq!(report.trans_cnt, 2);
+    assert_eq!(report.replayed_blocks, vec![5, 6]);
+}
+
+#[test]
+fn restart_with_other_handles_open_commits_after_they_stop() {
+    let dev = mkdev(1, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    let mut h = fs.journal.start(1).unwrap();
+    let mut other = fs.journal.start(1).unwrap();
+    h.write(block(5, 1)).unwrap();
+    other.write(block(6, 1)).unwrap();
+    let old = h.trans.clone();
+
+    // 旧事务被锁定等待 other 结束，重新开始的句柄加入新的事务
+    let h = h.restart(1).unwrap();
+    assert!(!Arc::ptr_eq(&old, &h.trans));
+    assert_eq!(old.borrow().state, TransState::Locked);
+
+    other.stop().unwrap();
+    assert_eq!(old.borrow().state, TransState::Committed);
+    h.stop().unwrap();
+}
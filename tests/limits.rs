@@ -0,0 +1,100 @@
+mod common;
+
+use common::*;
+use jbd2_rs::*;
+use std::sync::Arc;
+
+#[test]
+fn transaction_limit_follows_log_size_and_superblock() {
+    // 64 块的日志默认每个事务最多使用四分之一
+    let mut fs = mkfs(mkdev(1, 0));
+    fs.journal_start().unwrap();
+    assert_eq!(fs.journal.max_trans_buffers, 16);
+    assert!(fs.journal.start(17).is_err());
+
+    let mut fs = mkfs(mkdev(1, 0));
+    fs.sb.max_transaction = 8u32.to_be();
+    fs.journal_start().unwrap();
+    assert_eq!(fs.journal.max_trans_buffers, 8);
+
+    // 满额的事务连同描述符块和提交块必须能放进日志
+    let mut fs = mkfs(mkdev(1, 0));
+    fs.set_max_trans_fraction(1);
+    fs.journal_start().unwrap();
+    let max = fs.journal.max_trans_buffers;
+    assert!(fs.journal.jbd_log_blocks(max, 0) <= JOURNAL_MAXLEN - JOURNAL_FIRST);
+}
+
+#[test]
+fn handle_that_does_not_fit_starts_a_new_transaction() {
+    let mut fs = mkfs(mkdev(1, 0));
+    fs.journal_start().unwrap();
+
+    // 第一个句柄还没有写入任何块，第二个句柄同样不能让事务超过上限
+    let h1 = fs.journal.start(10).unwrap();
+    let h2 = fs.journal.start(10).unwrap();
+    assert!(!Arc::ptr_eq(&h1.trans, &h2.trans));
+    assert_eq!(h1.trans.borrow().state, TransState::Locked);
+    assert_eq!(h1.trans.borrow().outstanding_credits, 10);
+    assert_eq!(h2.trans.borrow().outstanding_credits, 10);
+
+    // 前一个事务还在等待句柄结束，运行的事务又放不下时返回错误而不是超过上限
+    assert!(fs.journal.start(10).is_err());
+    assert_eq!(h2.trans.borrow().outstanding_credits, 10);
+
+    h1.stop().unwrap();
+    let h3 = fs.journal.start(10).unwrap();
+    assert_eq!(h2.trans.borrow().state, TransState::Locked);
+    h2.stop().unwrap();
+    h3.stop().unwrap();
+}
+
+#[test]
+fn full_transaction_is_committed_automatically() {
+    let dev = mkdev(1, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    for i in 0..17 {
+        fs.write_trans(block(200 + i, 1)).unwrap();
+    }
+    assert_eq!(fs.curr_trans.as_ref().unwrap().borrow().data_cnt, 1);
+
+    let report = mkfs(dev.snapshot()).recover_dry_run().unwrap();
+    assert_eq!(report.trans_cnt, 1);
+    assert_eq!(report.planned_writes.len(), 16);
+}
+
+#[test]
+fn write_is_refused_when_full_while_previous_commit_waits() {
+    let mut fs = mkfs(mkdev(1, 0));
+    fs.journal_start().unwrap();
+
+    let mut h = fs.journal.start(1).unwrap();
+    h.write(block(5, 1)).unwrap();
+    fs.trans_stop().unwrap();
+
+    for i in 0..16 {
+        fs.write_trans(block(200 + i, 1)).unwrap();
+    }
+    assert!(fs.write_trans(block(300, 1)).is_err());
+    assert_eq!(fs.curr_trans.as_ref().unwrap().borrow().data_cnt, 16);
+
+    h.stop().unwrap();
+    fs.write_trans(block(300, 1)).unwrap();
+}
+
+#[test]
+fn data_limit_is_enforced_per_handle_write() {
+    let mut fs = mkfs(mkdev(1, 0));
+    fs.journal_start().unwrap();
+    fs.journal.max_trans_data = 2;
+
+    let mut h = fs.journal.start(4).unwrap();
+    h.write(block(5, 1)).unwrap();
+    h.write(block(6, 1)).unwrap();
+    assert!(h.write(block(7, 1)).is_err());
+    // 已经在事务中的块不受限制
+    h.write(block(6, 2)).unwrap();
+    h.stop().unwrap();
+}
@@ -0,0 +1,192 @@
+mod common;
+
+use common::*;
+use jbd2_rs::*;
+use std::sync::Arc;
+
+#[test]
+fn replay_reads_log_in_batches() {
+    let dev = mkdev(1, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    for trans in 0..3u32 {
+        for i in 0..12u32 {
+            fs.write_trans(block(400 - trans * 12 - i, trans as u8 + 1)).unwrap();
+        }
+        fs.trans_stop().unwrap();
+    }
+
+    let crash = dev.snapshot();
+    let mut fs = mkfs(crash.clone());
+    *crash.reads.lock().unwrap() = 0;
+    let report = fs.recover().unwrap();
+    assert_eq!(report.replayed_cnt, 36);
+    assert!(*crash.reads.lock().unwrap() <= 4);
+    assert_eq!(crash.block(400), vec![1; BLOCK_SIZE]);
+    assert_eq!(crash.block(365), vec![3; BLOCK_SIZE]);
+}
+
+#[test]
+fn transaction_with_bad_checksum_is_not_replayed() {
+    let dev = mkdev_with(JOURNAL_MAXLEN, 1, JBD_FEATURE_COMPAT_CHECKSUM, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    fs.write_trans(block(5, 0x11)).unwrap();
+    fs.trans_stop().unwrap();
+    fs.write_trans(block(6, 0x22)).unwrap();
+    fs.trans_stop().unwrap();
+
+    // 破坏第二个事务中块 6 的日志副本
+    let crash = dev.snapshot();
+    let report = mkfs(crash.clone()).recover_dry_run().unwrap();
+    let copy = report.planned_writes.iter().find(|w| w.block == 6).unwrap();
+    let offset = copy.journal_block as usize * BLOCK_SIZE;
+    crash.write_offset(offset, &[0x33; 16]);
+
+    let mut fs = mkfs(crash.clone());
+    let report = fs.recover().unwrap();
+    assert_eq!(report.csum_failures, 1);
+    assert_eq!(report.trans_cnt, 1);
+    assert_eq!(report.replayed_blocks, vec![5]);
+    assert_eq!(crash.block(6), vec![0; BLOCK_SIZE]);
+}
+
+#[test]
+fn log_and_transaction_ids_wrap_around() {
+    // 16 块的日志和接近 2^32 的事务 ID，提交 40 个事务后日志和 ID 都绕回
+    let dev = mkdev_with(JOURNAL_FIRST + 16, 0xffff_fff0, 0, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    for i in 0..40u32 {
+        fs.write_trans(block(10 + i % 5, i as u8)).unwrap();
+        fs.write_trans(block(20 + i % 3, i as u8)).unwrap();
+        fs.trans_stop().unwrap();
+    }
+
+    let crash = dev.snapshot();
+    let mut recovered = mkfs(crash.clone());
+    let report = recovered.recover().unwrap();
+    assert_eq!(report.end_trans_id, 0xffff_fff0u32.wrapping_add(39));
+    assert!(tid_gt(report.end_trans_id, report.start_trans_id));
+    for lba in [10, 11, 12, 13, 14, 20, 21, 22] {
+        assert_eq!(crash.block(lba), fs.read_block(lba));
+    }
+}
+
+#[test]
+fn recovery_report_describes_replayed_transactions() {
+    let dev = mkdev(1, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    fs.write_trans(block(2, 0x11)).unwrap();
+    fs.write_trans(block(3, 0x11)).unwrap();
+    fs.trans_stop().unwrap();
+    fs.write_trans(block(4, 0x22)).unwrap();
+    fs.trans_stop().unwrap();
+
+    let crash = dev.snapshot();
+    let report = mkfs(crash.clone()).recover().unwrap();
+    assert_eq!((report.start_trans_id, report.end_trans_id), (1, 2));
+    assert_eq!(report.trans_cnt, 2);
+    assert_eq!(report.replayed_cnt, 3);
+    assert_eq!(report.replayed_blocks, vec![2, 3, 4]);
+    assert!(!report.dry_run);
+    assert_eq!(crash.block(3), vec![0x11; BLOCK_SIZE]);
+    assert_eq!(crash.block(4), vec![0x22; BLOCK_SIZE]);
+}
+
+// 块 2 在事务 1..=4 中依次写入 1..=4，返回没有检查点的设备
+fn four_rewrites_of_block_2() -> Arc<MemDevice> {
+    let dev = mkdev(1, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+    for i in 1..=4u8 {
+        fs.write_trans(block(2, i)).unwrap();
+        fs.trans_stop().unwrap();
+    }
+    dev.snapshot()
+}
+
+#[test]
+fn dry_run_reports_planned_writes_only() {
+    let crash = four_rewrites_of_block_2();
+    let report = mkfs(crash.clone()).recover_dry_run().unwrap();
+    assert!(report.dry_run);
+    assert!(report.replayed_blocks.is_empty());
+    assert_eq!(report.planned_writes.len(), 1);
+    assert_eq!(report.planned_writes[0].trans_id, 4);
+
+    // 设备和超级块保持不变，之后仍然可以正常恢复
+    assert_eq!(crash.block(2), vec![0; BLOCK_SIZE]);
+    assert_ne!(mkfs(crash.clone()).sb.start, 0);
+    mkfs(crash.clone()).recover().unwrap();
+    assert_eq!(crash.block(2), vec![4; BLOCK_SIZE]);
+}
+
+#[test]
+fn recover_until_stops_at_transaction() {
+    let crash = four_rewrites_of_block_2();
+    let report = mkfs(crash.clone()).recover_until(2).unwrap();
+    assert_eq!(report.end_trans_id, 2);
+    assert_eq!(report.trans_cnt, 2);
+    assert_eq!(report.planned_writes[0].trans_id, 2);
+    assert_eq!(crash.block(2), vec![2; BLOCK_SIZE]);
+}
+
+#[test]
+fn recover_into_leaves_journal_device_untouched() {
+    let crash = four_rewrites_of_block_2();
+    let target = mkdev(1, 0);
+    let report = mkfs(crash.clone()).recover_into(target.clone()).unwrap();
+    assert_eq!(report.replayed_blocks, vec![2]);
+    assert_eq!(target.block(2), vec![4; BLOCK_SIZE]);
+    assert_eq!(crash.block(2), vec![0; BLOCK_SIZE]);
+    assert_ne!(mkfs(crash.clone()).sb.start, 0);
+}
+
+#[test]
+fn only_latest_copy_is_replayed() {
+    let crash = four_rewrites_of_block_2();
+    let report = mkfs(crash.clone()).recover().unwrap();
+    assert_eq!(report.trans_cnt, 4);
+    assert_eq!(report.superseded_cnt, 3);
+    assert_eq!(report.replayed_cnt, 1);
+    assert_eq!(report.planned_writes[0].trans_id, 4);
+    assert_eq!(crash.block(2), vec![4; BLOCK_SIZE]);
+}
+
+#[test]
+fn escaped_block_is_restored() {
+    let dev = mkdev(1, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    // 以日志魔数开头的块写入日志时魔数被清零，恢复时还原
+    let mut data = block(5, 0x22);
+    data.data[0..4].copy_from_slice(&JBD_MAGIC_NUMBER.to_be_bytes());
+    fs.write_trans(data.clone()).unwrap();
+    fs.trans_stop().unwrap();
+
+    let crash = dev.snapshot();
+    let mut fs = mkfs(crash.clone());
+    let report = fs.recover().unwrap();
+    assert!(report.planned_writes[0].is_escape);
+    assert_eq!(crash.block(5), data.data);
+}
+
+#[test]
+fn recovery_leaves_a_clean_log() {
+    let crash = four_rewrites_of_block_2();
+    let mut fs = mkfs(crash.clone());
+    fs.recover().unwrap();
+    assert_eq!(fs.sb.start, 0);
+
+    // 超级块已经落盘，重新挂载后不再需要恢复
+    let report = mkfs(crash.snapshot()).recover().unwrap();
+    assert_eq!(report.trans_cnt, 0);
+    assert_eq!(crash.block(2), vec![4; BLOCK_SIZE]);
+}
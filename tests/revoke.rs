@@ -0,0 +1,72 @@
+mod common;
+
+use common::*;
+use jbd2_rs::*;
+
+#[test]
+fn revoked_block_is_not_replayed() {
+    let dev = mkdev(1, JBD_FEATURE_INCOMPAT_REVOKE);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    fs.write_trans(block(5, 0x11)).unwrap();
+    fs.trans_stop().unwrap();
+    fs.revoke_trans(5).unwrap();
+    fs.trans_stop().unwrap();
+
+    let mut fs = mkfs(dev.snapshot());
+    let report = fs.recover().unwrap();
+    assert_eq!(report.trans_cnt, 2);
+    assert_eq!(report.revoked_cnt, 1);
+    assert!(report.replayed_blocks.is_empty());
+    assert_eq!(fs.read_block(5), vec![0; BLOCK_SIZE]);
+}
+
+#[test]
+fn block_rewritten_after_revoke_is_replayed() {
+    let dev = mkdev(1, JBD_FEATURE_INCOMPAT_REVOKE);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    fs.write_trans(block(5, 0x11)).unwrap();
+    fs.trans_stop().unwrap();
+
+    // 同一个事务中先撤销再重新写入，最新的内容必须能够恢复
+    fs.revoke_trans(5).unwrap();
+    fs.write_trans(block(5, 0x22)).unwrap();
+    fs.trans_stop().unwrap();
+
+    let mut fs = mkfs(dev.snapshot());
+    let report = fs.recover().unwrap();
+    assert_eq!(report.trans_cnt, 2);
+    assert_eq!(report.replayed_blocks, vec![5]);
+    assert_eq!(fs.read_block(5), vec![0x22; BLOCK_SIZE]);
+}
+
+#[test]
+fn revoke_forgets_block_written_in_same_transaction() {
+    let dev = mkdev(1, JBD_FEATURE_INCOMPAT_REVOKE);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    fs.write_trans(block(5, 0x11)).unwrap();
+    fs.write_trans(block(6, 0x22)).unwrap();
+    fs.revoke_trans(5).unwrap();
+    {
+        let trans = fs.curr_trans.as_ref().unwrap().borrow();
+        assert_eq!(trans.data_cnt, 1);
+        assert_eq!(trans.outstanding_credits, 1);
+    }
+    assert_eq!(fs.read_block(5), vec![0; BLOCK_SIZE]);
+    assert_eq!(fs.read_block(6), vec![0x22; BLOCK_SIZE]);
+    fs.trans_stop().unwrap();
+
+    // 恢复和检查点看到的内容一致：撤销的块都不写回
+    let report = mkfs(dev.snapshot()).recover_dry_run().unwrap();
+    assert_eq!(report.planned_writes.len(), 1);
+    assert_eq!(report.planned_writes[0].block, 6);
+
+    fs.journal_stop().unwrap();
+    assert_eq!(dev.block(5), vec![0; BLOCK_SIZE]);
+    assert_eq!(dev.block(6), vec![0x22; BLOCK_SIZE]);
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use common::*;
+use jbd2_rs::*;
+
+#[test]
+fn log_space_left_counts_reserved_credits() {
+    let mut fs = mkfs(mkdev(1, JBD_FEATURE_INCOMPAT_REVOKE));
+    fs.journal_start().unwrap();
+    let len = JOURNAL_MAXLEN - JOURNAL_FIRST;
+
+    // 即使没有事务也要为提交块留出位置
+    assert_eq!(fs.journal.log_space_left(), len - 1);
+
+    // 4 个数据块、1 个描述符块和 1 个提交块
+    let h = fs.journal.start(4).unwrap();
+    assert_eq!(fs.journal.log_space_left(), len - 6);
+
+    // 撤销记录需要一个撤销块
+    fs.revoke_trans(9).unwrap();
+    assert_eq!(fs.journal.log_space_left(), len - 7);
+
+    // 句柄结束后只剩撤销块和提交块
+    h.stop().unwrap();
+    assert_eq!(fs.journal.log_space_left(), len - 2);
+}
+
+#[test]
+fn wait_for_space_checkpoints_old_transactions() {
+    // 16 块的日志，一个事务最多 14 个数据块
+    let dev = mkdev_with(JOURNAL_FIRST + 16, 1, 0, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.set_max_trans_fraction(1);
+    fs.journal_start().unwrap();
+    assert_eq!(fs.journal.max_trans_buffers, 14);
+
+    for i in 0..10 {
+        fs.write_trans(block(200 + i, 1)).unwrap();
+    }
+    fs.trans_stop().unwrap();
+    assert_eq!(fs.journal.free, 4);
+    assert_eq!(dev.block(200), vec![0; BLOCK_SIZE]);
+
+    // 新句柄放不下时先对最老的事务做检查点，把它的块写回原位置
+    let h = fs.journal.start(4).unwrap();
+    assert!(fs.journal.cp_queue.queue.is_empty());
+    assert_eq!(dev.block(200), vec![1; BLOCK_SIZE]);
+    h.stop().unwrap();
+}
+
+#[test]
+fn extend_is_refused_when_the_log_is_full() {
+    let mut fs = mkfs(mkdev_with(JOURNAL_FIRST + 16, 1, 0, 0));
+    fs.set_max_trans_fraction(1);
+    fs.journal_start().unwrap();
+
+    // 锁定的事务还要占用 12 块，新的事务最多再预留 2 个数据块
+    let h1 = fs.journal.start(10).unwrap();
+    fs.trans_stop().unwrap();
+    let mut h2 = fs.journal.start(2).unwrap();
+    assert_eq!(fs.journal.log_space_left(), 0);
+    assert!(fs.journal.start(1).is_err());
+    assert!(h2.extend(1).is_err());
+    assert_eq!(h2.buffer_credits, 2);
+
+    h1.stop().unwrap();
+    h2.extend(1).unwrap();
+    h2.stop().unwrap();
+}
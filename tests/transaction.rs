@@ -0,0 +1,90 @@
+mod common;
+
+use common::*;
+use jbd2_rs::*;
+
+#[test]
+fn commit_waits_for_open_handles() {
+    let dev = mkdev(1, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    let mut h = fs.journal.start(1).unwrap();
+    h.write(block(5, 1)).unwrap();
+    let trans = h.trans.clone();
+    assert_eq!(trans.borrow().state, TransState::Running);
+
+    // 还有句柄时提交只锁定事务，最后一个句柄结束后才写日志
+    fs.trans_stop().unwrap();
+    assert_eq!(trans.borrow().state, TransState::Locked);
+    assert!(fs.curr_trans.is_none());
+    assert!(fs.commit_trans.is_some());
+
+    h.stop().unwrap();
+    assert_eq!(trans.borrow().state, TransState::Committed);
+    assert!(fs.commit_trans.is_none());
+}
+
+#[test]
+fn new_transaction_runs_while_previous_is_locked() {
+    let dev = mkdev(1, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    let mut h = fs.journal.start(1).unwrap();
+    h.write(block(5, 1)).unwrap();
+    fs.trans_stop().unwrap();
+
+    // 锁定的事务等待句柄期间，新的写入进入新的运行事务
+    fs.write_trans(block(6, 2)).unwrap();
+    let running = fs.curr_trans.clone().unwrap();
+    assert_eq!(running.borrow().state, TransState::Running);
+    assert!(!running.borrow().jbd_trans_has_block(5));
+
+    // 同一时刻只有一个事务在提交
+    assert!(fs.trans_stop().is_err());
+
+    h.stop().unwrap();
+    fs.trans_stop().unwrap();
+    assert_eq!(running.borrow().state, TransState::Committed);
+
+    let report = mkfs(dev.snapshot()).recover().unwrap();
+    assert_eq!(report.trans_cnt, 2);
+    assert_eq!(report.replayed_blocks, vec![5, 6]);
+}
+
+#[test]
+fn same_block_is_logged_once_per_transaction() {
+    let dev = mkdev(1, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+
+    fs.write_trans(block(5, 1)).unwrap();
+    fs.write_trans(block(5, 2)).unwrap();
+    fs.write_trans(block(5, 3)).unwrap();
+    {
+        let trans = fs.curr_trans.as_ref().unwrap().borrow();
+        assert_eq!(trans.data_cnt, 1);
+        assert_eq!(trans.outstanding_credits, 1);
+    }
+
+    // 句柄重复写同一个块也只消耗一个日志块
+    let mut h = fs.journal.start(1).unwrap();
+    h.write(block(5, 4)).unwrap();
+    h.write(block(5, 5)).unwrap();
+    assert_eq!(h.buffer_credits, 1);
+    h.stop().unwrap();
+
+    let free = fs.journal.free;
+    fs.trans_stop().unwrap();
+    // 描述符块、一个数据块和提交块
+    assert_eq!(free - fs.journal.free, 3);
+
+    assert_eq!(fs.read_block(5), vec![5; BLOCK_SIZE]);
+
+    let crash = dev.snapshot();
+    let report = mkfs(crash.clone()).recover().unwrap();
+    assert_eq!(report.planned_writes.len(), 1);
+    assert_eq!(report.superseded_cnt, 0);
+    assert_eq!(crash.block(5), vec![5; BLOCK_SIZE]);
+}
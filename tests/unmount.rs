@@ -0,0 +1,26 @@
+mod common;
+
+use common::*;
+use jbd2_rs::*;
+
+#[test]
+fn journal_stop_leaves_a_clean_log() {
+    let dev = mkdev(1, 0);
+    let mut fs = mkfs(dev.clone());
+    fs.journal_start().unwrap();
+    fs.write_trans(block(2, 0x11)).unwrap();
+    fs.trans_stop().unwrap();
+    fs.write_trans(block(3, 0x22)).unwrap();
+
+    // 还有句柄在运行时不能卸载
+    let h = fs.journal.start(1).unwrap();
+    assert!(fs.journal_stop().is_err());
+    h.stop().unwrap();
+
+    fs.journal_stop().unwrap();
+    assert_eq!(dev.block(2), vec![0x11; BLOCK_SIZE]);
+    assert_eq!(dev.block(3), vec![0x22; BLOCK_SIZE]);
+
+    let report = mkfs(dev.snapshot()).recover().unwrap();
+    assert_eq!(report.trans_cnt, 0);
+}